@@ -0,0 +1,220 @@
+//! Throughput and latency of the three `VersionedMap` backends
+//! side by side, at several key counts and key lengths.
+
+mod common;
+
+use criterion::measurement::WallTime;
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion, Throughput,
+};
+use tt::treelike::VMapTree;
+use tt::treelike_no_trie::VMapNoTrie;
+use tt::trivial::VMapTriv;
+use tt::VersionedMap;
+
+const KEY_COUNTS: [usize; 2] = [1_000, 10_000];
+const KEY_LENS: [usize; 2] = [16, 64];
+
+fn filled<M: VersionedMap<String, u64>>(new: fn() -> M, keys: &[String]) -> M {
+    let mut map = new();
+    for (i, key) in keys.iter().enumerate() {
+        map.insert(key.clone(), i as u64);
+    }
+    map
+}
+
+/// Runs `f` for every backend and every combination of key count and key length.
+fn for_each_case(
+    c: &mut Criterion,
+    name: &str,
+    f: impl Fn(&mut BenchmarkGroup<'_, WallTime>, &str, &[String]),
+) {
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+    for count in KEY_COUNTS {
+        for len in KEY_LENS {
+            let keys = common::keys(count, len);
+            group.throughput(Throughput::Elements(count as u64));
+            f(&mut group, &format!("{}x{}", count, len), &keys);
+        }
+    }
+    group.finish();
+}
+
+macro_rules! for_each_backend {
+    ($bench:ident, $group:expr, $param:expr, $keys:expr) => {
+        $bench($group, "triv", VMapTriv::new, $param, $keys);
+        $bench($group, "tree", VMapTree::new, $param, $keys);
+        $bench($group, "notrie", VMapNoTrie::new, $param, $keys);
+    };
+}
+
+fn insert<M: VersionedMap<String, u64>>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    backend: &str,
+    new: fn() -> M,
+    param: &str,
+    keys: &[String],
+) {
+    group.bench_with_input(BenchmarkId::new(backend, param), keys, |b, keys| {
+        b.iter_batched(
+            new,
+            |mut map| {
+                for (i, key) in keys.iter().enumerate() {
+                    map.insert(key.clone(), i as u64);
+                }
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn get<M: VersionedMap<String, u64>>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    backend: &str,
+    new: fn() -> M,
+    param: &str,
+    keys: &[String],
+) {
+    let map = filled(new, keys);
+    group.bench_with_input(BenchmarkId::new(backend, param), keys, |b, keys| {
+        b.iter(|| keys.iter().filter_map(|key| map.get(key)).sum::<u64>())
+    });
+}
+
+fn remove<M: VersionedMap<String, u64>>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    backend: &str,
+    new: fn() -> M,
+    param: &str,
+    keys: &[String],
+) {
+    group.bench_with_input(BenchmarkId::new(backend, param), keys, |b, keys| {
+        b.iter_batched(
+            || filled(new, keys),
+            |mut map| {
+                for key in keys {
+                    map.remove(key);
+                }
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn checkpoint<M: VersionedMap<String, u64>>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    backend: &str,
+    new: fn() -> M,
+    param: &str,
+    keys: &[String],
+) {
+    let mut map = filled(new, keys);
+    group.bench_function(BenchmarkId::new(backend, param), |b| {
+        // reusing the tag drops the previous snapshot, so memory stays flat
+        b.iter(|| map.checkpoint("TAG".to_owned()))
+    });
+}
+
+/// Rollback to a snapshot that differs from the live state in a tenth of the keys.
+fn rollback<M: VersionedMap<String, u64>>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    backend: &str,
+    new: fn() -> M,
+    param: &str,
+    keys: &[String],
+) {
+    group.bench_with_input(BenchmarkId::new(backend, param), keys, |b, keys| {
+        b.iter_batched(
+            || {
+                let mut map = filled(new, keys);
+                map.checkpoint("BASE".to_owned());
+                for key in keys.iter().step_by(10) {
+                    map.insert(key.clone(), u64::MAX);
+                }
+                map
+            },
+            |mut map| {
+                assert!(map.rollback("BASE".to_owned()));
+                map
+            },
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+/// Read-heavy traffic: 80% gets, 15% inserts and 5% removes, with a checkpoint
+/// every 1000 operations and a rollback to the previous one every 5000.
+fn mixed<M: VersionedMap<String, u64>>(
+    group: &mut BenchmarkGroup<'_, WallTime>,
+    backend: &str,
+    new: fn() -> M,
+    param: &str,
+    keys: &[String],
+) {
+    group.bench_with_input(BenchmarkId::new(backend, param), keys, |b, keys| {
+        b.iter_batched(
+            || filled(new, keys),
+            |mut map| {
+                // xorshift, so that every backend sees the same sequence
+                let mut state = 0x2545_F491_4F6C_DD1D_u64;
+                for op in 1..=keys.len() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    let key = &keys[state as usize % keys.len()];
+                    match state % 100 {
+                        0..=79 => {
+                            map.get(key);
+                        }
+                        80..=94 => {
+                            map.insert(key.clone(), state);
+                        }
+                        _ => {
+                            map.remove(key);
+                        }
+                    }
+                    if op % 1000 == 0 {
+                        map.checkpoint((op / 1000).to_string());
+                    }
+                    if op % 5000 == 0 {
+                        map.rollback((op / 1000 - 1).to_string());
+                    }
+                }
+                map
+            },
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn throughput(c: &mut Criterion) {
+    for_each_case(c, "insert", |group, param, keys| {
+        for_each_backend!(insert, group, param, keys);
+    });
+    for_each_case(c, "get", |group, param, keys| {
+        for_each_backend!(get, group, param, keys);
+    });
+    for_each_case(c, "remove", |group, param, keys| {
+        for_each_backend!(remove, group, param, keys);
+    });
+    for_each_case(c, "mixed", |group, param, keys| {
+        for_each_backend!(mixed, group, param, keys);
+    });
+}
+
+fn latency(c: &mut Criterion) {
+    for_each_case(c, "checkpoint", |group, param, keys| {
+        group.throughput(Throughput::Elements(1));
+        for_each_backend!(checkpoint, group, param, keys);
+    });
+    for_each_case(c, "rollback", |group, param, keys| {
+        group.throughput(Throughput::Elements(1));
+        for_each_backend!(rollback, group, param, keys);
+    });
+}
+
+criterion_group!(benches, throughput, latency);
+criterion_main!(benches);
@@ -0,0 +1,9 @@
+/// `count` distinct keys of `len` bytes, sharing a common prefix the way
+/// namespaced keys do and differing in a scrambled 8 byte suffix.
+pub fn keys(count: usize, len: usize) -> Vec<String> {
+    assert!(len >= 8 && count <= u32::MAX as usize);
+    let prefix = "n".repeat(len - 8);
+    (0..count as u32)
+        .map(|i| format!("{}{:08x}", prefix, i.wrapping_mul(0x9E37_79B9)))
+        .collect()
+}
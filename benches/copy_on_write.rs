@@ -0,0 +1,159 @@
+//! Write throughput of `VMapTree` with and without snapshots sharing
+//! the trie: without them the nodes on a key's path are mutated in place,
+//! with them each write path-copies the nodes it touches for the first time.
+//! Also compares filling a map one key at a time with the batch and bulk
+//! loading APIs.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use tt::treelike::VMapTree;
+use tt::VersionedMap;
+
+const KEY_COUNTS: [usize; 3] = [100, 1_000, 10_000];
+
+fn keys(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| format!("key/{:016x}", i.wrapping_mul(0x9E37_79B9_7F4A_7C15)))
+        .collect()
+}
+
+fn filled(keys: &[String]) -> VMapTree<String, u64> {
+    let mut map = VMapTree::new();
+    for (i, key) in keys.iter().enumerate() {
+        map.insert(key.clone(), i as u64);
+    }
+    map
+}
+
+fn overwrite(map: &mut VMapTree<String, u64>, keys: &[String]) {
+    for (i, key) in keys.iter().enumerate() {
+        map.insert(key.clone(), !(i as u64));
+    }
+}
+
+fn writes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree_overwrite");
+    group.sample_size(10);
+
+    for count in KEY_COUNTS {
+        let keys = keys(count);
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_with_input(BenchmarkId::new("no_snapshots", count), &keys, |b, keys| {
+            b.iter_batched(
+                || filled(keys),
+                |mut map| {
+                    overwrite(&mut map, keys);
+                    map
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(
+            BenchmarkId::new("live_snapshot", count),
+            &keys,
+            |b, keys| {
+                b.iter_batched(
+                    || {
+                        let mut map = filled(keys);
+                        map.checkpoint("LIVE".to_owned());
+                        map
+                    },
+                    |mut map| {
+                        overwrite(&mut map, keys);
+                        map
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("live_snapshot_batch", count),
+            &keys,
+            |b, keys| {
+                b.iter_batched(
+                    || {
+                        let mut map = filled(keys);
+                        map.checkpoint("LIVE".to_owned());
+                        map
+                    },
+                    |mut map| {
+                        let entries = keys.iter().enumerate();
+                        map.insert_batch(entries.map(|(i, key)| (key.clone(), !(i as u64))));
+                        map
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+
+        group.bench_with_input(
+            BenchmarkId::new("snapshot_every_100_writes", count),
+            &keys,
+            |b, keys| {
+                b.iter_batched(
+                    || filled(keys),
+                    |mut map| {
+                        for (i, chunk) in keys.chunks(100).enumerate() {
+                            map.checkpoint(i.to_string());
+                            overwrite(&mut map, chunk);
+                        }
+                        map
+                    },
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn fill(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tree_fill");
+    group.sample_size(10);
+
+    for count in KEY_COUNTS {
+        let mut entries: Vec<(String, u64)> = keys(count).into_iter().zip(0..).collect();
+        entries.sort_unstable();
+        group.throughput(Throughput::Elements(count as u64));
+
+        group.bench_with_input(BenchmarkId::new("insert", count), &entries, |b, entries| {
+            b.iter_batched(
+                || entries.clone(),
+                |entries| {
+                    let mut map = VMapTree::new();
+                    for (key, v) in entries {
+                        map.insert(key, v);
+                    }
+                    map
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("insert_batch", count), &entries, |b, entries| {
+            b.iter_batched(
+                || entries.clone(),
+                |entries| {
+                    let mut map = VMapTree::new();
+                    map.insert_batch(entries);
+                    map
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_with_input(BenchmarkId::new("bulk_load", count), &entries, |b, entries| {
+            b.iter_batched(
+                || entries.clone(),
+                VMapTree::bulk_load,
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, writes, fill);
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+//! Heap usage of the three `VersionedMap` backends, measured with a counting
+//! global allocator: bytes per live key, and bytes each additional snapshot
+//! costs when 1% of the keys changed since the previous one.
+//!
+//! Prints a table rather than going through criterion, which only measures time.
+
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tt::treelike::VMapTree;
+use tt::treelike_no_trie::VMapNoTrie;
+use tt::trivial::VMapTriv;
+use tt::VersionedMap;
+
+const KEY_COUNTS: [usize; 3] = [1_000, 10_000, 50_000];
+const KEY_LENS: [usize; 2] = [16, 64];
+const SNAPSHOTS: usize = 10;
+
+struct Counting;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATED.fetch_add(new_size, Ordering::Relaxed);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn allocated() -> usize {
+    ALLOCATED.load(Ordering::Relaxed)
+}
+
+fn measure<M: VersionedMap<String, u64>>(backend: &str, new: fn() -> M) {
+    for count in KEY_COUNTS {
+        for len in KEY_LENS {
+            let keys = common::keys(count, len);
+            let churn = (count / 100).max(1);
+
+            let before = allocated();
+            let mut map = new();
+            for (i, key) in keys.iter().enumerate() {
+                map.insert(key.clone(), i as u64);
+            }
+            let live = allocated() - before;
+
+            for round in 0..SNAPSHOTS {
+                map.checkpoint(round.to_string());
+                for key in keys.iter().skip(round * churn).take(churn) {
+                    map.insert(key.clone(), u64::MAX);
+                }
+            }
+            let per_snapshot = (allocated() - before - live) / SNAPSHOTS;
+
+            println!(
+                "{:<8} {:>8} {:>8} {:>14} {:>10} {:>14}",
+                backend,
+                count,
+                len,
+                live,
+                live / count,
+                per_snapshot
+            );
+            drop(map);
+        }
+    }
+}
+
+fn main() {
+    println!(
+        "{:<8} {:>8} {:>8} {:>14} {:>10} {:>14}",
+        "backend", "keys", "key_len", "live_bytes", "bytes/key", "bytes/snapshot"
+    );
+    measure("triv", VMapTriv::new);
+    measure("tree", VMapTree::new);
+    measure("notrie", VMapNoTrie::new);
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// raw binary keys, the trie branches on every byte value
+fuzz_target!(|data: &[u8]| {
+    tt_fuzz::run(tt_fuzz::decode(data, |key| key));
+});
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// invalid UTF-8 in the input ends up as replacement characters,
+// multi-byte characters exercise the trie paths of `String` keys
+fuzz_target!(|data: &[u8]| {
+    tt_fuzz::run(tt_fuzz::decode(data, |key| {
+        String::from_utf8_lossy(&key).into_owned()
+    }));
+});
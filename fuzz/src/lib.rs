@@ -0,0 +1,161 @@
+//! Decoding of fuzzer input into operation sequences, and a runner
+//! asserting all `VersionedMap` implementations agree on them.
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use tt::treelike::{AsFromBytes, VMapTree};
+use tt::treelike_no_trie::VMapNoTrie;
+use tt::trivial::VMapTriv;
+use tt::VersionedMap;
+
+// a length byte with this value announces a key longer than a byte can say
+const LONG_KEY: u8 = u8::MAX;
+const TAGS: [&str; 4] = ["t0", "t1", "t2", "t3"];
+
+type Backend<K> = (&'static str, Box<dyn VersionedMap<K, u32>>);
+
+#[derive(Debug)]
+pub enum Op<K> {
+    Insert(K, u32),
+    Remove(K),
+    Checkpoint(String),
+    Rollback(String),
+    Prune,
+}
+
+struct Cursor<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Cursor<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (first, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(*first)
+    }
+
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let (taken, rest) = self.data.split_at(len.min(self.data.len()));
+        self.data = rest;
+        taken
+    }
+
+    /// Keys are never empty, `VMapTree` doesn't support those.
+    fn key(&mut self) -> Option<Vec<u8>> {
+        let len = self.byte()?;
+        let key = if len == LONG_KEY {
+            // up to 16k bytes repeating a short pattern, which the input
+            // couldn't hold verbatim
+            let repeat = self.byte()? as usize * 16;
+            let pattern = self.take(4);
+            pattern
+                .iter()
+                .copied()
+                .cycle()
+                .take(repeat * pattern.len())
+                .collect()
+        } else {
+            self.take(len as usize % 32).to_vec()
+        };
+        if key.is_empty() {
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    fn tag(&mut self) -> Option<String> {
+        Some(TAGS[self.byte()? as usize % TAGS.len()].to_owned())
+    }
+}
+
+/// Reads operations until the input runs out. Raw key bytes are turned into
+/// `K` with `make_key`.
+pub fn decode<K>(data: &[u8], make_key: impl Fn(Vec<u8>) -> K) -> Vec<Op<K>> {
+    let mut cursor = Cursor { data };
+    let mut ops = vec![];
+    while let Some(opcode) = cursor.byte() {
+        let op = match opcode % 8 {
+            0..=2 => match (cursor.key(), cursor.byte()) {
+                (Some(key), Some(val)) => Op::Insert(make_key(key), val as u32),
+                _ => continue,
+            },
+            3 => match cursor.key() {
+                Some(key) => Op::Remove(make_key(key)),
+                None => continue,
+            },
+            4 | 5 => match cursor.tag() {
+                Some(tag) => Op::Checkpoint(tag),
+                None => continue,
+            },
+            6 => match cursor.tag() {
+                Some(tag) => Op::Rollback(tag),
+                None => continue,
+            },
+            _ => Op::Prune,
+        };
+        ops.push(op);
+    }
+    ops
+}
+
+/// Applies `ops` to every backend, asserting they return the same for every
+/// operation and agree on every key touched so far after it.
+pub fn run<K>(ops: Vec<Op<K>>)
+where
+    K: AsFromBytes + Eq + Hash + Clone + Debug + 'static,
+{
+    let mut maps: Vec<Backend<K>> = vec![
+        ("triv", Box::new(VMapTriv::new())),
+        ("tree", Box::new(VMapTree::new())),
+        ("notrie", Box::new(VMapNoTrie::new())),
+    ];
+    let mut keys = HashSet::new();
+
+    for op in ops {
+        let results: Vec<String> = maps
+            .iter_mut()
+            .map(|(_, map)| match &op {
+                Op::Insert(k, v) => format!("{:?}", map.insert(k.clone(), *v)),
+                Op::Remove(k) => format!("{:?}", map.remove(k)),
+                Op::Checkpoint(tag) => {
+                    map.checkpoint(tag.clone());
+                    String::new()
+                }
+                Op::Rollback(tag) => format!("{:?}", map.rollback(tag.clone())),
+                Op::Prune => {
+                    map.prune();
+                    String::new()
+                }
+            })
+            .collect();
+        for ((name, _), result) in maps.iter().zip(&results).skip(1) {
+            assert_eq!(
+                &results[0], result,
+                "{} disagrees with triv on {:?}",
+                name, op
+            );
+        }
+
+        match op {
+            Op::Insert(k, _) | Op::Remove(k) => {
+                keys.insert(k);
+            }
+            _ => {}
+        }
+        for k in &keys {
+            let expected = maps[0].1.get(k);
+            for (name, map) in &maps[1..] {
+                assert_eq!(
+                    expected,
+                    map.get(k),
+                    "{} disagrees with triv on {:?}",
+                    name,
+                    k
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use tt::Change;
+
+use crate::json::Json;
+use crate::store::Store;
+
+pub enum Command {
+    Get(String),
+    Put(String, String),
+    Rm(String),
+    Ls(Option<String>),
+    Tags,
+    Checkpoint(String),
+    Rollback(String),
+    Diff(String, String),
+    Prune,
+    Stats,
+}
+
+pub const NAMES: [&str; 10] = [
+    "get",
+    "put",
+    "rm",
+    "ls",
+    "tags",
+    "checkpoint",
+    "rollback",
+    "diff",
+    "prune",
+    "stats",
+];
+
+pub const USAGE: &str = "\
+commands:
+    get KEY               value of KEY
+    put KEY VALUE         set KEY, printing the value it replaced
+    rm KEY                remove KEY, printing the value it had
+    ls [PREFIX]           entries, only the keys starting with PREFIX if given
+    tags                  tags of the snapshots
+    checkpoint TAG        snapshot the current state as TAG
+    rollback TAG          restore the state snapshotted as TAG
+    diff FROM TO          changes between the snapshots FROM and TO
+    prune                 drop all snapshots
+    stats                 sizes of the map";
+
+fn key(word: &str) -> Result<String, String> {
+    if word.is_empty() {
+        return Err("keys can't be empty".to_owned());
+    }
+    Ok(word.to_owned())
+}
+
+impl Command {
+    pub fn parse(words: &[String]) -> Result<Command, String> {
+        let args: Vec<&str> = words.iter().map(String::as_str).collect();
+        let command = match args[..] {
+            ["get", k] => Command::Get(key(k)?),
+            ["put", k, v] => Command::Put(key(k)?, v.to_owned()),
+            ["rm", k] => Command::Rm(key(k)?),
+            ["ls"] => Command::Ls(None),
+            ["ls", prefix] => Command::Ls(Some(prefix.to_owned())),
+            ["tags"] => Command::Tags,
+            ["checkpoint", tag] => Command::Checkpoint(tag.to_owned()),
+            ["rollback", tag] => Command::Rollback(tag.to_owned()),
+            ["diff", from, to] => Command::Diff(from.to_owned(), to.to_owned()),
+            ["prune"] => Command::Prune,
+            ["stats"] => Command::Stats,
+            [] => return Err("no command given".to_owned()),
+            [name, ..] if NAMES.contains(&name) => {
+                return Err(format!("wrong arguments to {}, see --help", name))
+            }
+            [name, ..] => return Err(format!("unknown command {:?}, see --help", name)),
+        };
+        Ok(command)
+    }
+
+    /// Whether running the command may change the map.
+    pub fn writes(&self) -> bool {
+        matches!(
+            self,
+            Command::Put(..)
+                | Command::Rm(_)
+                | Command::Checkpoint(_)
+                | Command::Rollback(_)
+                | Command::Prune
+        )
+    }
+
+    pub fn run(self, store: &mut dyn Store) -> Result<Output, String> {
+        let output = match self {
+            Command::Get(k) => Output::Value(store.get(&k).cloned()),
+            Command::Put(k, v) => Output::Value(store.insert(k, v)),
+            Command::Rm(k) => Output::Value(store.remove(&k)),
+            Command::Ls(prefix) => {
+                let prefix = prefix.unwrap_or_default();
+                let mut entries: Vec<(String, String)> = store
+                    .entries()
+                    .into_iter()
+                    .filter(|(k, _)| k.starts_with(&prefix))
+                    .map(|(k, v)| (k, v.clone()))
+                    .collect();
+                entries.sort_unstable();
+                Output::Entries(entries)
+            }
+            Command::Tags => Output::Tags(store.tags().into_iter().map(str::to_owned).collect()),
+            Command::Checkpoint(tag) => {
+                store.checkpoint(tag);
+                Output::Done
+            }
+            Command::Rollback(tag) => {
+                if !store.rollback(tag.clone()) {
+                    return Err(format!("unknown tag {:?}", tag));
+                }
+                Output::Done
+            }
+            Command::Diff(from, to) => match store.diff(&from, &to) {
+                Some(mut changes) => {
+                    changes.sort_by(|a, b| a.key().cmp(b.key()));
+                    Output::Changes(changes)
+                }
+                None => return Err(format!("unknown tag {:?} or {:?}", from, to)),
+            },
+            Command::Prune => {
+                store.prune();
+                Output::Done
+            }
+            Command::Stats => Output::Stats(store.stats()),
+        };
+        Ok(output)
+    }
+}
+
+pub enum Output {
+    Done,
+    Value(Option<String>),
+    Entries(Vec<(String, String)>),
+    Tags(Vec<String>),
+    Changes(Vec<Change<String, String>>),
+    Stats(Vec<(&'static str, usize)>),
+}
+
+impl Output {
+    /// One item per line, empty for [`Output::Done`].
+    pub fn plain(&self) -> String {
+        let lines: Vec<String> = match self {
+            Output::Done => vec![],
+            Output::Value(None) => vec!["(nil)".to_owned()],
+            Output::Value(Some(v)) => vec![v.clone()],
+            Output::Entries(entries) => entries
+                .iter()
+                .map(|(k, v)| format!("{}\t{}", k, v))
+                .collect(),
+            Output::Tags(tags) => tags.clone(),
+            Output::Changes(changes) => changes
+                .iter()
+                .map(|change| match change {
+                    Change::Inserted { key, value } => format!("+ {}\t{}", key, value),
+                    Change::Removed { key, old } => format!("- {}\t{}", key, old),
+                    Change::Updated { key, old, new } => {
+                        format!("~ {}\t{} -> {}", key, old, new)
+                    }
+                })
+                .collect(),
+            Output::Stats(stats) => stats
+                .iter()
+                .map(|(name, n)| format!("{}\t{}", name, n))
+                .collect(),
+        };
+        lines.join("\n")
+    }
+
+    pub fn json(&self) -> Json {
+        match self {
+            Output::Done => Json::Obj(vec![("ok", Json::Bool(true))]),
+            Output::Value(v) => Json::Obj(vec![(
+                "value",
+                v.as_deref().map_or(Json::Null, Json::str),
+            )]),
+            Output::Entries(entries) => Json::Arr(
+                entries
+                    .iter()
+                    .map(|(k, v)| Json::Obj(vec![("key", Json::str(k)), ("value", Json::str(v))]))
+                    .collect(),
+            ),
+            Output::Tags(tags) => Json::Arr(tags.iter().map(|tag| Json::str(tag)).collect()),
+            Output::Changes(changes) => Json::Arr(
+                changes
+                    .iter()
+                    .map(|change| match change {
+                        Change::Inserted { key, value } => Json::Obj(vec![
+                            ("change", Json::str("inserted")),
+                            ("key", Json::str(key)),
+                            ("value", Json::str(value)),
+                        ]),
+                        Change::Removed { key, old } => Json::Obj(vec![
+                            ("change", Json::str("removed")),
+                            ("key", Json::str(key)),
+                            ("old", Json::str(old)),
+                        ]),
+                        Change::Updated { key, old, new } => Json::Obj(vec![
+                            ("change", Json::str("updated")),
+                            ("key", Json::str(key)),
+                            ("old", Json::str(old)),
+                            ("new", Json::str(new)),
+                        ]),
+                    })
+                    .collect(),
+            ),
+            Output::Stats(stats) => {
+                Json::Obj(stats.iter().map(|(name, n)| (*name, Json::Num(*n))).collect())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Command, Output};
+    use crate::store::{Backend, Store};
+
+    fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+        Command::parse(&words)
+    }
+
+    fn run(store: &mut dyn Store, line: &str) -> Result<Output, String> {
+        parse(line)?.run(store)
+    }
+
+    fn json(store: &mut dyn Store, line: &str) -> String {
+        run(store, line).unwrap().json().to_string()
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Err("no command given".to_owned()), parse("").map(|_| ()));
+        for line in ["get", "put k", "rm a b", "ls a b", "tags x", "diff before", "prune x"] {
+            let name = line.split(' ').next().unwrap();
+            assert_eq!(
+                Err(format!("wrong arguments to {}, see --help", name)),
+                parse(line).map(|_| ())
+            );
+        }
+        assert_eq!(
+            Err("unknown command \"set\", see --help".to_owned()),
+            parse("set k v").map(|_| ())
+        );
+        let empty_key = Command::parse(&["put".to_owned(), String::new(), "v".to_owned()]);
+        assert_eq!(Err("keys can't be empty".to_owned()), empty_key.map(|_| ()));
+    }
+
+    #[test]
+    fn parses_arguments() {
+        assert!(matches!(parse("put k v"), Ok(Command::Put(k, v)) if k == "k" && v == "v"));
+        assert!(matches!(parse("ls"), Ok(Command::Ls(None))));
+        assert!(matches!(parse("ls user/"), Ok(Command::Ls(Some(p))) if p == "user/"));
+        assert!(matches!(parse("diff a b"), Ok(Command::Diff(a, b)) if a == "a" && b == "b"));
+        assert!(parse("put k v").unwrap().writes());
+        assert!(!parse("diff a b").unwrap().writes());
+    }
+
+    #[test]
+    fn unknown_tags() {
+        let mut store = Backend::Tree.empty();
+        store.checkpoint("before".to_owned());
+        assert_eq!(
+            Err("unknown tag \"after\"".to_owned()),
+            run(&mut *store, "rollback after").map(|_| ())
+        );
+        assert_eq!(
+            Err("unknown tag \"before\" or \"after\"".to_owned()),
+            run(&mut *store, "diff before after").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn json_output() {
+        let mut store = Backend::Tree.empty();
+        run(&mut *store, "put user/2 b").unwrap();
+        run(&mut *store, "put user/1 a").unwrap();
+        run(&mut *store, "put group/1 \"c").unwrap();
+        run(&mut *store, "checkpoint before").unwrap();
+        run(&mut *store, "put user/1 x").unwrap();
+        run(&mut *store, "rm user/2").unwrap();
+        run(&mut *store, "put user/3 c").unwrap();
+        run(&mut *store, "checkpoint after").unwrap();
+
+        assert_eq!(
+            r#"[{"key":"user/1","value":"x"},{"key":"user/3","value":"c"}]"#,
+            json(&mut *store, "ls user/")
+        );
+        assert_eq!(
+            r#"[{"key":"group/1","value":"\"c"},{"key":"user/1","value":"x"},{"key":"user/3","value":"c"}]"#,
+            json(&mut *store, "ls")
+        );
+        assert_eq!(
+            concat!(
+                r#"[{"change":"updated","key":"user/1","old":"a","new":"x"},"#,
+                r#"{"change":"removed","key":"user/2","old":"b"},"#,
+                r#"{"change":"inserted","key":"user/3","value":"c"}]"#
+            ),
+            json(&mut *store, "diff before after")
+        );
+        assert_eq!(r#"{"value":"x"}"#, json(&mut *store, "get user/1"));
+        assert_eq!(r#"{"value":null}"#, json(&mut *store, "get user/2"));
+        assert_eq!(r#"{"ok":true}"#, json(&mut *store, "prune"));
+
+        let stats = Output::Stats(vec![("keys", 3), ("heap_bytes", 100)]);
+        assert_eq!(r#"{"keys":3,"heap_bytes":100}"#, stats.json().to_string());
+        let stats = json(&mut *store, "stats");
+        assert!(stats.starts_with(r#"{"keys":3,"snapshots":0,"prefix_snapshots":0,"nodes":"#), "{}", stats);
+    }
+}
@@ -0,0 +1,105 @@
+//! Inspects and edits a map saved with `tt::persist`, one command at a
+//! time or from an interactive shell.
+
+mod command;
+mod json;
+mod repl;
+mod store;
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use command::Command;
+use json::Json;
+use store::Backend;
+
+const USAGE: &str = "\
+usage: tt [--json] [--backend triv|tree|notrie] FILE COMMAND [ARGS...]
+       tt [--json] [--backend triv|tree|notrie] [FILE]
+
+    --json        print results and errors as JSON
+    --backend     map to load FILE into, tree by default; FILE is
+                  created by the first command writing to it
+
+Without a command, starts a shell over the map in FILE, or over an empty
+one. The shell saves only when told to.";
+
+struct Args {
+    json: bool,
+    backend: Backend,
+    file: Option<PathBuf>,
+    command: Vec<String>,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut json = false;
+    let mut backend = Backend::Tree;
+    let file = loop {
+        match args.next().as_deref() {
+            Some("--json") => json = true,
+            Some("--backend") => {
+                backend = args.next().ok_or("--backend needs a value")?.parse()?;
+            }
+            Some("-h" | "--help") => return Err(String::new()),
+            Some(flag) if flag.starts_with("--") => {
+                return Err(format!("unknown option {:?}", flag))
+            }
+            Some(file) => break Some(PathBuf::from(file)),
+            None => break None,
+        }
+    };
+    Ok(Args {
+        json,
+        backend,
+        file,
+        command: args.collect(),
+    })
+}
+
+fn run(file: &Path, args: &Args) -> Result<String, String> {
+    let command = Command::parse(&args.command)?;
+    let writes = command.writes();
+    let mut store = store::open(file, args.backend)
+        .map_err(|err| format!("can't load {}: {}", file.display(), err))?;
+    let output = command.run(&mut *store)?;
+    if writes {
+        store::save(file, &*store)
+            .map_err(|err| format!("can't save {}: {}", file.display(), err))?;
+    }
+    Ok(if args.json {
+        output.json().to_string()
+    } else {
+        output.plain()
+    })
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) if msg.is_empty() => {
+            println!("{}\n\n{}", USAGE, command::USAGE);
+            return;
+        }
+        Err(msg) => {
+            eprintln!("tt: {}\n\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+    let result = match &args.file {
+        Some(file) if !args.command.is_empty() => run(file, &args),
+        file => repl::run(file.clone(), args.backend, args.json).map(|_| String::new()),
+    };
+    match result {
+        Ok(out) if out.is_empty() => {}
+        Ok(out) => println!("{}", out),
+        Err(msg) if args.json => {
+            println!("{}", Json::Obj(vec![("error", Json::Str(msg))]));
+            process::exit(1);
+        }
+        Err(msg) => {
+            eprintln!("tt: {}", msg);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use std::cell::RefCell;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::command::{self, Command};
+use crate::store::{self, Backend, Store};
+
+const HELP: &str = "\
+shell commands:
+    backend [NAME]        show the backend, or move the map to another one
+    save [FILE]           save to FILE, or to the file the shell was opened with
+    help                  this text
+    quit                  leave, also on ctrl-d
+
+Arguments with spaces go in double quotes, `\\\"` and `\\\\` escape inside them.";
+
+const SHELL_NAMES: [&str; 4] = ["backend", "save", "help", "quit"];
+
+type Shared = Rc<RefCell<Box<dyn Store>>>;
+
+/// Splits `line` on whitespace, keeping double-quoted parts together.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while matches!(chars.peek(), Some(c) if c.is_whitespace()) {
+            chars.next();
+        }
+        let mut word = String::new();
+        match chars.peek() {
+            None => return Ok(words),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        None => return Err("unterminated quote".to_owned()),
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated quote".to_owned()),
+                        },
+                        Some(c) => word.push(c),
+                    }
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.peek().filter(|c| !c.is_whitespace()) {
+                    word.push(*c);
+                    chars.next();
+                }
+            }
+        }
+        words.push(word);
+    }
+}
+
+/// Completes command names, tags and keys of the map being explored.
+struct ShellHelper {
+    store: Shared,
+}
+
+impl ShellHelper {
+    fn candidates(&self, words: &[&str], partial: &str) -> Vec<String> {
+        let store = self.store.borrow();
+        let pool: Vec<String> = match words {
+            [] => command::NAMES
+                .iter()
+                .chain(&SHELL_NAMES)
+                .map(|name| name.to_string())
+                .collect(),
+            ["checkpoint" | "rollback"] | ["diff"] | ["diff", _] => {
+                store.tags().into_iter().map(str::to_owned).collect()
+            }
+            ["get" | "put" | "rm" | "ls"] => store.entries().into_iter().map(|(k, _)| k).collect(),
+            ["backend"] => Backend::NAMES.iter().map(|name| name.to_string()).collect(),
+            _ => vec![],
+        };
+        let mut matching: Vec<String> = pool
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(partial))
+            .collect();
+        matching.sort_unstable();
+        matching
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        Ok((start, self.candidates(&words, &line[start..])))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+struct Shell {
+    store: Shared,
+    backend: Backend,
+    file: Option<PathBuf>,
+    json: bool,
+}
+
+impl Shell {
+    fn execute(&mut self, words: &[String]) -> Result<String, String> {
+        let args: Vec<&str> = words.iter().map(String::as_str).collect();
+        match args[..] {
+            ["help"] => Ok(format!("{}\n\n{}", command::USAGE, HELP)),
+            ["backend"] => Ok(self.backend.name().to_owned()),
+            ["backend", name] => {
+                let backend: Backend = name.parse()?;
+                let converted = store::convert(&**self.store.borrow(), backend);
+                *self.store.borrow_mut() = converted;
+                self.backend = backend;
+                Ok(String::new())
+            }
+            ["save"] => match self.file.clone() {
+                Some(file) => self.save(&file),
+                None => Err("no file to save to, give one".to_owned()),
+            },
+            ["save", file] => self.save(Path::new(file)),
+            _ => {
+                let output = Command::parse(words)?.run(&mut **self.store.borrow_mut())?;
+                Ok(if self.json {
+                    output.json().to_string()
+                } else {
+                    output.plain()
+                })
+            }
+        }
+    }
+
+    fn save(&self, file: &Path) -> Result<String, String> {
+        store::save(file, &**self.store.borrow())
+            .map_err(|err| format!("can't save {}: {}", file.display(), err))?;
+        Ok(format!("saved to {}", file.display()))
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(".tt_history"))
+}
+
+/// Reads and runs commands until quit, over the map saved in `file` if
+/// given, an empty one otherwise. Nothing is saved unless asked to.
+pub fn run(file: Option<PathBuf>, backend: Backend, json: bool) -> Result<(), String> {
+    let store = match &file {
+        Some(file) => store::open(file, backend)
+            .map_err(|err| format!("can't load {}: {}", file.display(), err))?,
+        None => backend.empty(),
+    };
+    let store = Rc::new(RefCell::new(store));
+    let mut editor: Editor<ShellHelper, DefaultHistory> =
+        Editor::new().map_err(|err| err.to_string())?;
+    editor.set_helper(Some(ShellHelper {
+        store: store.clone(),
+    }));
+    let history = history_file();
+    if let Some(history) = &history {
+        // there's none yet on the first run
+        let _ = editor.load_history(history);
+    }
+    let mut shell = Shell {
+        store,
+        backend,
+        file,
+        json,
+    };
+
+    loop {
+        let prompt = format!("tt[{}]> ", shell.backend.name());
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.to_string()),
+        };
+        let words = match split_words(&line) {
+            Ok(words) if words.is_empty() => continue,
+            Ok(words) => words,
+            Err(msg) => {
+                eprintln!("{}", msg);
+                continue;
+            }
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        if words == ["quit"] || words == ["exit"] {
+            break;
+        }
+        match shell.execute(&words) {
+            Ok(out) if out.is_empty() => {}
+            Ok(out) => println!("{}", out),
+            Err(msg) => eprintln!("{}", msg),
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use rustyline::completion::Completer;
+    use rustyline::history::DefaultHistory;
+    use rustyline::Context;
+
+    use super::{split_words, ShellHelper};
+    use crate::store::Backend;
+
+    #[test]
+    fn completes_by_position() {
+        let mut store = Backend::Tree.empty();
+        store.insert("user/1".to_owned(), "a".to_owned());
+        store.insert("user/2".to_owned(), "b".to_owned());
+        store.insert("group/1".to_owned(), "c".to_owned());
+        store.checkpoint("before".to_owned());
+        store.checkpoint("baseline".to_owned());
+        let helper = ShellHelper {
+            store: Rc::new(RefCell::new(store)),
+        };
+
+        assert_eq!(vec!["backend"], helper.candidates(&[], "ba"));
+        assert_eq!(vec!["user/1", "user/2"], helper.candidates(&["get"], "us"));
+        assert_eq!(vec!["baseline"], helper.candidates(&["diff", "before"], "ba"));
+        assert!(helper.candidates(&["put", "user/1"], "").is_empty());
+    }
+
+    #[test]
+    fn completes_after_multibyte_whitespace() {
+        let mut store = Backend::Tree.empty();
+        store.insert("user/1".to_owned(), "a".to_owned());
+        let helper = ShellHelper {
+            store: Rc::new(RefCell::new(store)),
+        };
+        let history = DefaultHistory::new();
+        let ctx = Context::new(&history);
+
+        let line = "get\u{a0}us";
+        let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+        assert_eq!("get\u{a0}".len(), start);
+        assert_eq!(vec!["user/1"], candidates);
+
+        let line = "get\u{3000}";
+        let (start, candidates) = helper.complete(line, line.len(), &ctx).unwrap();
+        assert_eq!(line.len(), start);
+        assert_eq!(vec!["user/1"], candidates);
+    }
+
+    #[test]
+    fn quoted_words_stay_together() {
+        assert_eq!(
+            Ok(vec![
+                "put".to_owned(),
+                "a b".to_owned(),
+                "say \"hi\"".to_owned(),
+                "c\\d".to_owned(),
+            ]),
+            split_words(r#"  put "a b"   "say \"hi\"" c\d "#)
+        );
+        assert!(split_words(r#"put "a b"#).is_err());
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use std::str::FromStr;
+
+use tt::treelike::VMapTree;
+use tt::treelike_no_trie::VMapNoTrie;
+use tt::trivial::VMapTriv;
+use tt::{persist, Diff, VersionedMap};
+
+/// A map of strings, whichever the backend.
+pub trait Store: VersionedMap<String, String> + Diff<String, String> {
+    fn stats(&self) -> Vec<(&'static str, usize)>;
+}
+
+impl Store for VMapTriv<String, String> {
+    fn stats(&self) -> Vec<(&'static str, usize)> {
+        let stats = VMapTriv::stats(self);
+        vec![
+            ("keys", stats.keys),
+            ("snapshots", stats.snapshots),
+            ("stored_entries", stats.stored_entries),
+            ("heap_bytes", stats.heap_bytes),
+        ]
+    }
+}
+
+impl Store for VMapTree<String, String> {
+    fn stats(&self) -> Vec<(&'static str, usize)> {
+        let stats = VMapTree::stats(self);
+        vec![
+            ("keys", stats.keys),
+            ("snapshots", stats.snapshots),
+            ("prefix_snapshots", stats.prefix_snapshots),
+            ("nodes", stats.nodes),
+            ("unique_nodes", stats.unique_nodes),
+            ("heap_bytes", stats.heap_bytes),
+        ]
+    }
+}
+
+impl Store for VMapNoTrie<String, String> {
+    fn stats(&self) -> Vec<(&'static str, usize)> {
+        let stats = VMapNoTrie::stats(self);
+        vec![
+            ("keys", stats.keys),
+            ("snapshots", stats.snapshots),
+            ("version_entries", stats.version_entries),
+            ("link_entries", stats.link_entries),
+            ("heap_bytes", stats.heap_bytes),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Triv,
+    Tree,
+    NoTrie,
+}
+
+impl Backend {
+    pub const NAMES: [&'static str; 3] = ["triv", "tree", "notrie"];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Triv => "triv",
+            Backend::Tree => "tree",
+            Backend::NoTrie => "notrie",
+        }
+    }
+
+    pub fn empty(self) -> Box<dyn Store> {
+        match self {
+            Backend::Triv => Box::new(VMapTriv::new()),
+            Backend::Tree => Box::new(VMapTree::new()),
+            Backend::NoTrie => Box::new(VMapNoTrie::new()),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "triv" => Ok(Backend::Triv),
+            "tree" => Ok(Backend::Tree),
+            "notrie" => Ok(Backend::NoTrie),
+            _ => Err(format!(
+                "unknown backend {:?}, expected one of {}",
+                s,
+                Backend::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+/// Loads the map saved at `path` into `backend`, an empty one if there's
+/// no such file yet.
+pub fn open(path: &Path, backend: Backend) -> io::Result<Box<dyn Store>> {
+    let mut store = backend.empty();
+    match File::open(path) {
+        Ok(file) => persist::load(&mut *store, BufReader::new(file))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+    Ok(store)
+}
+
+/// Copies `store` with all its snapshots into an empty `backend`.
+pub fn convert(store: &dyn Store, backend: Backend) -> Box<dyn Store> {
+    let mut bytes = vec![];
+    persist::save(store, &mut bytes).expect("writing to memory doesn't fail");
+    let mut converted = backend.empty();
+    persist::load(&mut *converted, &bytes[..]).expect("what was just saved loads");
+    converted
+}
+
+/// Saves `store` to `path` through a temporary file, so that a failure
+/// midway leaves the previous contents intact.
+pub fn save(path: &Path, store: &dyn Store) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let file = File::create(&tmp)?;
+    persist::save(store, BufWriter::new(file))?;
+    fs::rename(&tmp, path)
+}
@@ -0,0 +1,361 @@
+//! Moving a snapshot between maps by shipping only its difference from
+//! another snapshot both maps have.
+//!
+//! States are identified by a SHA-256 hash of their entries, sorted by
+//! key bytes, so that maps of different backends agree on it.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use sha2::{Digest, Sha256};
+
+use crate::persist::{invalid, read_bytes, read_len, read_value, write_bytes, write_len};
+use crate::treelike::AsFromBytes;
+use crate::{Change, Diff, Hash, VersionedMap};
+
+const MAGIC: &[u8; 4] = b"TTDB";
+const FORMAT_VERSION: u8 = 1;
+
+const PUT: u8 = 0;
+const DEL: u8 = 1;
+
+type State = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// The changes turning the snapshot `base_tag` into `target_tag`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bundle {
+    pub base_tag: String,
+    pub target_tag: String,
+    pub base_hash: Hash,
+    pub target_hash: Hash,
+    // key bytes, and value bytes or `None` to remove the key
+    ops: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+impl Bundle {
+    /// Number of keys the bundle sets or removes.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[FORMAT_VERSION])?;
+        write_bytes(&mut out, self.base_tag.as_bytes())?;
+        write_bytes(&mut out, self.target_tag.as_bytes())?;
+        out.write_all(&self.base_hash)?;
+        out.write_all(&self.target_hash)?;
+        write_len(&mut out, self.ops.len())?;
+        for (k, v) in &self.ops {
+            match v {
+                Some(v) => {
+                    out.write_all(&[PUT])?;
+                    write_bytes(&mut out, k)?;
+                    write_bytes(&mut out, v)?;
+                }
+                None => {
+                    out.write_all(&[DEL])?;
+                    write_bytes(&mut out, k)?;
+                }
+            }
+        }
+        out.flush()
+    }
+
+    /// Reads what [`Bundle::write_to`] wrote, malformed input is reported
+    /// as [`io::ErrorKind::InvalidData`].
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<Bundle> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a delta bundle"));
+        }
+        let mut version = [0; 1];
+        input.read_exact(&mut version)?;
+        if version[0] != FORMAT_VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+        let base_tag = read_value(&mut input)?;
+        let target_tag = read_value(&mut input)?;
+        let mut base_hash = [0; 32];
+        input.read_exact(&mut base_hash)?;
+        let mut target_hash = [0; 32];
+        input.read_exact(&mut target_hash)?;
+        let mut ops = vec![];
+        for _ in 0..read_len(&mut input)? {
+            let mut op = [0; 1];
+            input.read_exact(&mut op)?;
+            let k = read_bytes(&mut input)?;
+            let v = match op[0] {
+                PUT => Some(read_bytes(&mut input)?),
+                DEL => None,
+                _ => return Err(invalid("unknown operation")),
+            };
+            ops.push((k, v));
+        }
+        Ok(Bundle {
+            base_tag,
+            target_tag,
+            base_hash,
+            target_hash,
+            ops,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportError {
+    /// The receiving map has no snapshot with the base tag.
+    UnknownBase(String),
+    /// The receiving map's base snapshot isn't the one the bundle was
+    /// exported against.
+    BaseMismatch { expected: Hash, found: Hash },
+    /// Applying the bundle doesn't give the state it was exported from,
+    /// it got corrupted on the way.
+    TargetMismatch,
+    /// A key or value doesn't read back as the type of the receiving map.
+    Unreadable,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::UnknownBase(tag) => write!(f, "no snapshot tagged {:?}", tag),
+            ImportError::BaseMismatch { .. } => write!(f, "base snapshot differs"),
+            ImportError::TargetMismatch => write!(f, "bundle doesn't produce its target"),
+            ImportError::Unreadable => write!(f, "unreadable key or value"),
+        }
+    }
+}
+
+impl Error for ImportError {}
+
+fn hash(state: &State) -> Hash {
+    let mut hasher = Sha256::new();
+    for (k, v) in state {
+        hasher.update((k.len() as u64).to_le_bytes());
+        hasher.update(k);
+        hasher.update((v.len() as u64).to_le_bytes());
+        hasher.update(v);
+    }
+    hasher.finalize().into()
+}
+
+fn tagged_state<M, K, V>(map: &M, tag: &str) -> Option<State>
+where
+    M: VersionedMap<K, V> + Diff<K, V> + ?Sized,
+    K: AsFromBytes,
+    V: AsFromBytes + Clone,
+{
+    let changes = map.diff_live(tag)?;
+    let mut state: State = map
+        .entries()
+        .into_iter()
+        .map(|(k, v)| (k.as_bytes().to_vec(), v.as_bytes().to_vec()))
+        .collect();
+    for change in changes {
+        match change {
+            Change::Inserted { key, value }
+            | Change::Updated {
+                key, new: value, ..
+            } => {
+                state.insert(key.as_bytes().to_vec(), value.as_bytes().to_vec());
+            }
+            Change::Removed { key, .. } => {
+                state.remove(key.as_bytes());
+            }
+        }
+    }
+    Some(state)
+}
+
+/// Content hash of the snapshot `tag`, the same whatever the backend.
+pub fn snapshot_hash<M, K, V>(map: &M, tag: &str) -> Option<Hash>
+where
+    M: VersionedMap<K, V> + Diff<K, V> + ?Sized,
+    K: AsFromBytes,
+    V: AsFromBytes + Clone,
+{
+    tagged_state(map, tag).map(|state| hash(&state))
+}
+
+/// Bundles the changes from the snapshot `base_tag` to `target_tag`,
+/// `None` if either is unknown.
+pub fn export_delta<M, K, V>(map: &M, base_tag: &str, target_tag: &str) -> Option<Bundle>
+where
+    M: VersionedMap<K, V> + Diff<K, V> + ?Sized,
+    K: AsFromBytes,
+    V: AsFromBytes + Clone,
+{
+    let changes = map.diff(base_tag, target_tag)?;
+    let mut ops: Vec<(Vec<u8>, Option<Vec<u8>>)> = changes
+        .into_iter()
+        .map(|change| match change {
+            Change::Inserted { key, value }
+            | Change::Updated {
+                key, new: value, ..
+            } => (key.as_bytes().to_vec(), Some(value.as_bytes().to_vec())),
+            Change::Removed { key, .. } => (key.as_bytes().to_vec(), None),
+        })
+        .collect();
+    // the same delta bundles the same whatever the backend
+    ops.sort_unstable();
+    Some(Bundle {
+        base_tag: base_tag.to_owned(),
+        target_tag: target_tag.to_owned(),
+        base_hash: snapshot_hash(map, base_tag)?,
+        target_hash: snapshot_hash(map, target_tag)?,
+        ops,
+    })
+}
+
+/// Recreates the bundle's target snapshot in `map`, which has to hold the
+/// base snapshot the bundle was exported against, replacing any snapshot
+/// tagged the same. The live state is left as it is. On error `map` isn't
+/// changed.
+pub fn import_delta<M, K, V>(map: &mut M, bundle: &Bundle) -> Result<(), ImportError>
+where
+    M: VersionedMap<K, V> + Diff<K, V> + ?Sized,
+    K: AsFromBytes + Clone,
+    V: AsFromBytes + Clone,
+{
+    let mut state = tagged_state(map, &bundle.base_tag)
+        .ok_or_else(|| ImportError::UnknownBase(bundle.base_tag.clone()))?;
+    let found = hash(&state);
+    if found != bundle.base_hash {
+        return Err(ImportError::BaseMismatch {
+            expected: bundle.base_hash,
+            found,
+        });
+    }
+    for (k, v) in &bundle.ops {
+        match v {
+            Some(v) => state.insert(k.clone(), v.clone()),
+            None => state.remove(k),
+        };
+    }
+    if hash(&state) != bundle.target_hash {
+        return Err(ImportError::TargetMismatch);
+    }
+
+    // the live state is turned into the base first, then into the target
+    let to_base = map
+        .diff_live(&bundle.base_tag)
+        .expect("the base is known")
+        .into_iter()
+        .map(|change| match change {
+            Change::Inserted { key, value }
+            | Change::Updated {
+                key, new: value, ..
+            } => (key, Some(value)),
+            Change::Removed { key, .. } => (key, None),
+        });
+    let to_target = bundle
+        .ops
+        .iter()
+        .map(|(k, v)| {
+            let k = K::read_from(k).ok_or(ImportError::Unreadable)?;
+            let v = match v {
+                Some(v) => Some(V::read_from(v).ok_or(ImportError::Unreadable)?),
+                None => None,
+            };
+            Ok((k, v))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    map.checkpoint_with(
+        bundle.target_tag.clone(),
+        to_base.chain(to_target).collect(),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_delta, import_delta, snapshot_hash, Bundle, ImportError};
+    use crate::treelike::VMapTree;
+    use crate::treelike_no_trie::VMapNoTrie;
+    use crate::trivial::VMapTriv;
+    use crate::VersionedMap;
+
+    fn base(map: &mut dyn VersionedMap<String, String>) {
+        for (k, v) in [("a", "1"), ("ab", "2"), ("b", "3")] {
+            map.insert(k.to_owned(), v.to_owned());
+        }
+        map.checkpoint("BASE".to_owned());
+    }
+
+    fn exporter() -> VMapTree<String, String> {
+        let mut map = VMapTree::new();
+        base(&mut map);
+        map.insert("ab".to_owned(), "20".to_owned());
+        map.remove(&"b".to_owned());
+        map.insert("c".to_owned(), "4".to_owned());
+        map.checkpoint("TARGET".to_owned());
+        map.insert("d".to_owned(), "5".to_owned());
+        map
+    }
+
+    #[test]
+    fn target_is_recreated_on_other_backends() {
+        let source = exporter();
+        let bundle = export_delta(&source, "BASE", "TARGET").unwrap();
+        assert_eq!(3, bundle.len());
+        assert!(export_delta(&source, "BASE", "UNKNOWN").is_none());
+
+        let mut bytes = vec![];
+        bundle.write_to(&mut bytes).unwrap();
+        let bundle = Bundle::read_from(&bytes[..]).unwrap();
+
+        let mut receiver = VMapNoTrie::new();
+        base(&mut receiver);
+        receiver.insert("live".to_owned(), "only".to_owned());
+        import_delta(&mut receiver, &bundle).unwrap();
+
+        assert_eq!(vec!["BASE", "TARGET"], receiver.tags());
+        assert_eq!(Some(&"only".to_owned()), receiver.get(&"live".to_owned()));
+        assert_eq!(
+            snapshot_hash(&source, "TARGET"),
+            snapshot_hash(&receiver, "TARGET")
+        );
+        assert!(receiver.rollback("TARGET".to_owned()));
+        assert_eq!(Some(&"20".to_owned()), receiver.get(&"ab".to_owned()));
+        assert_eq!(None, receiver.get(&"b".to_owned()));
+        assert_eq!(None, receiver.get(&"live".to_owned()));
+    }
+
+    #[test]
+    fn mismatching_bases_are_refused() {
+        let bundle = export_delta(&exporter(), "BASE", "TARGET").unwrap();
+
+        let mut empty = VMapTriv::<String, String>::new();
+        assert_eq!(
+            Err(ImportError::UnknownBase("BASE".to_owned())),
+            import_delta(&mut empty, &bundle)
+        );
+
+        let mut other = VMapTriv::new();
+        other.insert("a".to_owned(), "other".to_owned());
+        other.checkpoint("BASE".to_owned());
+        assert!(matches!(
+            import_delta(&mut other, &bundle),
+            Err(ImportError::BaseMismatch { .. })
+        ));
+        assert_eq!(vec!["BASE"], other.tags());
+
+        let mut corrupt = bundle.clone();
+        corrupt.ops.pop();
+        let mut receiver = VMapTriv::new();
+        base(&mut receiver);
+        assert_eq!(
+            Err(ImportError::TargetMismatch),
+            import_delta(&mut receiver, &corrupt)
+        );
+        assert_eq!(vec!["BASE"], receiver.tags());
+    }
+}
@@ -1,10 +1,24 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+pub mod bundle;
+pub mod lsm;
+pub mod persist;
+pub mod replication;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod treelike;
 pub mod treelike_no_trie;
 pub mod trivial;
+pub mod watch;
 
 #[cfg(test)]
 pub(crate) mod test_helpers;
 
+/// SHA-256 digest, identifying contents.
+pub type Hash = [u8; 32];
+
 pub trait VersionedMap<K, V: Clone> {
     fn insert(&mut self, k: K, v: V) -> Option<V>;
     fn get(&self, k: &K) -> Option<&V>;
@@ -12,5 +26,130 @@ pub trait VersionedMap<K, V: Clone> {
 
     fn checkpoint(&mut self, tag: String);
     fn rollback(&mut self, tag: String) -> bool;
+    /// Checkpoints the current state under `save_as`, or under a generated
+    /// tag if it's `None`, and rolls back to `tag`, in one step, so that
+    /// the state rolled back from can be restored. Returns the tag the
+    /// state was saved under, `None` if `tag` is unknown and nothing changed.
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String>;
     fn prune(&mut self);
+
+    /// Checkpoints as `tag` the current state with `ops` applied to it,
+    /// each setting a key or removing it on `None`, leaving the current
+    /// state as it is.
+    ///
+    /// The default applies `ops` to the current state, checkpoints, and
+    /// undoes them, all through `insert` and `remove`, so maps which do
+    /// more than store on writes should override it.
+    fn checkpoint_with(&mut self, tag: String, ops: Vec<(K, Option<V>)>)
+    where
+        K: Clone,
+    {
+        let mut undo = vec![];
+        for (k, v) in ops {
+            let old = match v {
+                Some(v) => self.insert(k.clone(), v),
+                None => self.remove(&k),
+            };
+            undo.push((k, old));
+        }
+        self.checkpoint(tag);
+        for (k, old) in undo.into_iter().rev() {
+            match old {
+                Some(v) => self.insert(k, v),
+                None => self.remove(&k),
+            };
+        }
+    }
+
+    /// Tags of the snapshots held, sorted.
+    fn tags(&self) -> Vec<&str>;
+    /// Current entries, in no particular order.
+    fn entries(&self) -> Vec<(K, &V)>;
+}
+
+/// Difference in one key between two states of a map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    Inserted { key: K, value: V },
+    Removed { key: K, old: V },
+    Updated { key: K, old: V, new: V },
+}
+
+impl<K, V> Change<K, V> {
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Inserted { key, .. }
+            | Change::Removed { key, .. }
+            | Change::Updated { key, .. } => key,
+        }
+    }
+}
+
+pub trait Diff<K, V> {
+    /// Changes rolling back to `tag` would make to the current state,
+    /// in no particular order. `None` if `tag` is unknown.
+    fn diff_live(&self, tag: &str) -> Option<Vec<Change<K, V>>>;
+    /// Changes between the states tagged `from` and `to`, in no particular
+    /// order. `None` if either tag is unknown.
+    fn diff(&self, from: &str, to: &str) -> Option<Vec<Change<K, V>>>;
+}
+
+/// The change turning `old` into `new`, if they differ.
+pub(crate) fn change<K, V>(key: K, old: Option<&V>, new: Option<&V>) -> Option<Change<K, V>>
+where
+    V: Clone + PartialEq,
+{
+    match (old, new) {
+        (None, None) => None,
+        (None, Some(value)) => Some(Change::Inserted {
+            key,
+            value: value.clone(),
+        }),
+        (Some(old), None) => Some(Change::Removed {
+            key,
+            old: old.clone(),
+        }),
+        (Some(old), Some(new)) if old == new => None,
+        (Some(old), Some(new)) => Some(Change::Updated {
+            key,
+            old: old.clone(),
+            new: new.clone(),
+        }),
+    }
+}
+
+/// First of `autosave-0`, `autosave-1`, ... which isn't `taken`.
+pub(crate) fn autosave_tag(taken: impl Fn(&str) -> bool) -> String {
+    (0..)
+        .map(|n: u64| format!("autosave-{}", n))
+        .find(|tag| !taken(tag))
+        .unwrap()
+}
+
+/// `Debug` of the backends, showing the live entries and the tags.
+pub(crate) fn fmt_map<M, K, V>(map: &M, name: &str, f: &mut fmt::Formatter<'_>) -> fmt::Result
+where
+    M: VersionedMap<K, V>,
+    K: fmt::Debug,
+    V: Clone + fmt::Debug,
+{
+    struct Entries<'a, K, V>(Vec<(K, &'a V)>);
+
+    impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Entries<'_, K, V> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_map().entries(self.0.iter().map(|(k, v)| (k, v))).finish()
+        }
+    }
+
+    f.debug_struct(name)
+        .field("entries", &Entries(map.entries()))
+        .field("tags", &map.tags())
+        .finish()
+}
+
+/// Approximate size of the table backing `map`, not counting memory owned
+/// by the keys and values themselves.
+pub(crate) fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    // a control byte per slot on top of the slot itself
+    map.capacity() * (mem::size_of::<(K, V)>() + 1)
 }
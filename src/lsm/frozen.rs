@@ -0,0 +1,113 @@
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+
+// chunk `i` holds `FIRST_CHUNK << i` values, more than enough chunks to
+// never run out
+const FIRST_CHUNK: usize = 16;
+const CHUNKS: usize = 48;
+
+/// Values read from disk, keyed by key bytes, which can be added through
+/// `&self` and are handed out by reference until removed through
+/// `&mut self`. Values never move once added, as chunks are only
+/// allocated and never grown, so a forgotten value is only dropped when
+/// all are, once they outnumber the ones still held.
+pub(super) struct Frozen<V> {
+    chunks: [OnceCell<Box<[OnceCell<V>]>>; CHUNKS],
+    len: Cell<usize>,
+    index: RefCell<HashMap<Vec<u8>, usize>>,
+}
+
+fn locate(i: usize) -> (usize, usize) {
+    let n = i / FIRST_CHUNK + 1;
+    let chunk = (usize::BITS - 1 - n.leading_zeros()) as usize;
+    (chunk, i - FIRST_CHUNK * ((1 << chunk) - 1))
+}
+
+impl<V> Frozen<V> {
+    pub fn new() -> Self {
+        Frozen {
+            chunks: [(); CHUNKS].map(|_| OnceCell::new()),
+            len: Cell::new(0),
+            index: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn slot(&self, i: usize) -> &OnceCell<V> {
+        let (chunk, offset) = locate(i);
+        let chunk = self.chunks[chunk]
+            .get_or_init(|| (0..FIRST_CHUNK << chunk).map(|_| OnceCell::new()).collect());
+        &chunk[offset]
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        let i = *self.index.borrow().get(key)?;
+        self.slot(i).get()
+    }
+
+    pub fn insert(&self, key: Vec<u8>, v: V) -> &V {
+        let i = self.len.get();
+        self.len.set(i + 1);
+        self.index.borrow_mut().insert(key, i);
+        let slot = self.slot(i);
+        let _ = slot.set(v);
+        slot.get().unwrap()
+    }
+
+    pub fn forget(&mut self, key: &[u8]) {
+        let index = self.index.get_mut();
+        if index.remove(key).is_some() && self.len.get() > 2 * index.len() + FIRST_CHUNK {
+            self.clear();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Frozen::new();
+    }
+}
+
+#[cfg(test)]
+mod frozen_tests {
+    use super::{locate, Frozen, FIRST_CHUNK};
+
+    #[test]
+    fn chunks_are_contiguous() {
+        assert_eq!((0, 0), locate(0));
+        assert_eq!((0, FIRST_CHUNK - 1), locate(FIRST_CHUNK - 1));
+        assert_eq!((1, 0), locate(FIRST_CHUNK));
+        assert_eq!((2, 0), locate(3 * FIRST_CHUNK));
+
+        let frozen = Frozen::new();
+        let values: Vec<&usize> = (0..1000)
+            .map(|i| frozen.insert(i.to_string().into_bytes(), i))
+            .collect();
+        for (i, v) in values.into_iter().enumerate() {
+            assert_eq!(i, *v);
+            assert_eq!(Some(&i), frozen.get(i.to_string().as_bytes()));
+        }
+    }
+
+    #[test]
+    fn forgotten_values_are_dropped() {
+        let mut frozen = Frozen::new();
+        for i in 0..1000 {
+            frozen.insert(b"key".to_vec(), i);
+            frozen.forget(b"key");
+            assert!(frozen.len.get() <= FIRST_CHUNK + 1);
+        }
+
+        let mut frozen = Frozen::new();
+        for i in 0..100 {
+            frozen.insert(i.to_string().into_bytes(), i);
+        }
+        // dropped once there are more slots than twice the values held
+        // and a chunk
+        for i in 0..58 {
+            frozen.forget(i.to_string().as_bytes());
+        }
+        assert_eq!(100, frozen.len.get());
+        assert_eq!(Some(&99), frozen.get(b"99"));
+        frozen.forget(b"58");
+        assert_eq!(0, frozen.len.get());
+        assert_eq!(None, frozen.get(b"99"));
+    }
+}
@@ -0,0 +1,88 @@
+//! What a directory holds beyond the write-ahead log: the segments in
+//! use, and the versions of the tags and of the rollbacks.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::persist::{invalid, read_len, read_value, write_bytes, write_len};
+
+const MAGIC: &[u8; 4] = b"TTLM";
+const FORMAT_VERSION: u8 = 1;
+
+pub(super) const FILE_NAME: &str = "MANIFEST";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Manifest {
+    pub current_ver: u64,
+    pub next_segment: u64,
+    // oldest first
+    pub segments: Vec<u64>,
+    pub snapshots: HashMap<String, u64>,
+    // `(this, linked_to)` of every rollback, in order
+    pub links: Vec<(u64, u64)>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Manifest {
+            current_ver: 1,
+            next_segment: 0,
+            segments: vec![],
+            snapshots: HashMap::new(),
+            links: vec![],
+        }
+    }
+
+    /// Replaces the manifest in `dir` in one step.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        let tmp = dir.join(format!("{}.tmp", FILE_NAME));
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[FORMAT_VERSION])?;
+        out.write_all(&self.current_ver.to_le_bytes())?;
+        out.write_all(&self.next_segment.to_le_bytes())?;
+        write_len(&mut out, self.segments.len())?;
+        for id in &self.segments {
+            out.write_all(&id.to_le_bytes())?;
+        }
+        write_len(&mut out, self.snapshots.len())?;
+        for (tag, version) in &self.snapshots {
+            write_bytes(&mut out, tag.as_bytes())?;
+            out.write_all(&version.to_le_bytes())?;
+        }
+        write_len(&mut out, self.links.len())?;
+        for (this, linked_to) in &self.links {
+            out.write_all(&this.to_le_bytes())?;
+            out.write_all(&linked_to.to_le_bytes())?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&tmp, dir.join(FILE_NAME))
+    }
+
+    pub fn load(dir: &Path) -> io::Result<Manifest> {
+        let mut input = BufReader::new(File::open(dir.join(FILE_NAME))?);
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != FORMAT_VERSION {
+            return Err(invalid("not a manifest"));
+        }
+        let mut manifest = Manifest::new();
+        manifest.current_ver = read_len(&mut input)?;
+        manifest.next_segment = read_len(&mut input)?;
+        for _ in 0..read_len(&mut input)? {
+            manifest.segments.push(read_len(&mut input)?);
+        }
+        for _ in 0..read_len(&mut input)? {
+            let tag = read_value(&mut input)?;
+            manifest.snapshots.insert(tag, read_len(&mut input)?);
+        }
+        for _ in 0..read_len(&mut input)? {
+            manifest
+                .links
+                .push((read_len(&mut input)?, read_len(&mut input)?));
+        }
+        Ok(manifest)
+    }
+}
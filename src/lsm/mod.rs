@@ -0,0 +1,755 @@
+//! A map kept in a directory as a log-structured merge tree.
+//!
+//! Writes go to a write-ahead log and to an in-memory memtable, which is
+//! flushed to an immutable segment file once it's large enough. Every
+//! record carries the version it was written at, and every segment the
+//! range of versions its records cover. As in
+//! [`VMapNoTrie`](crate::treelike_no_trie::VMapNoTrie), a checkpoint only
+//! notes the current version under its tag and moves on to the next, and
+//! a rollback links a new version to the one rolled back to, so nothing
+//! already written is touched. Compaction merges the segments, keeping
+//! the records the live state and the tagged ones still read.
+
+mod frozen;
+mod manifest;
+mod segment;
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use frozen::Frozen;
+use manifest::Manifest;
+use segment::{Merged, Record, Segment};
+
+use crate::persist::invalid;
+use crate::treelike::AsFromBytes;
+
+const WAL_FILE_NAME: &str = "wal";
+
+/// Values by key bytes and version, `None` for a removal.
+type Memtable<V> = BTreeMap<(Vec<u8>, u64), Option<V>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Options {
+    /// records the memtable holds before being flushed to a segment
+    pub memtable_entries: usize,
+    /// segments kept before they're compacted into one
+    pub max_segments: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            memtable_entries: 4096,
+            max_segments: 8,
+        }
+    }
+}
+
+/// A [`VersionedMap`](crate::VersionedMap) stored in a directory. Values
+/// read from segments are kept in memory for
+/// [`get`](crate::VersionedMap::get) and
+/// [`entries`](crate::VersionedMap::entries) to hand them out by
+/// reference, until the memtable is flushed or the map rolled back. Until
+/// then, that is at most about twice as many values as there are live
+/// keys, as each write drops the value its key had.
+///
+/// The methods of [`VersionedMap`](crate::VersionedMap) panic on I/O
+/// errors, as it has no way to report them.
+pub struct VMapLsm<K, V> {
+    dir: PathBuf,
+    options: Options,
+    manifest: Manifest,
+    // oldest first
+    segments: Vec<Segment>,
+    memtable: Memtable<V>,
+    wal: File,
+    cache: Frozen<V>,
+    _phantom_data: PhantomData<K>,
+}
+
+/// Where the value of a key was found.
+enum Found<'a, V> {
+    Memtable(Option<&'a V>),
+    Segment(Option<Vec<u8>>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub memtable_entries: usize,
+    pub segments: usize,
+    /// size of the segment files
+    pub segment_bytes: u64,
+}
+
+fn check<T>(result: io::Result<T>) -> T {
+    result.unwrap_or_else(|e| panic!("LSM map I/O failed: {}", e))
+}
+
+fn read<T: AsFromBytes>(bytes: &[u8]) -> io::Result<T> {
+    T::read_from(bytes).ok_or_else(|| invalid("unreadable key or value"))
+}
+
+impl<K, V> VMapLsm<K, V>
+where
+    V: AsFromBytes,
+{
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        VMapLsm::open_with(dir, Options::default())
+    }
+
+    /// Opens the map in `dir`, creating it if missing. Writes logged since
+    /// the last flush are read back into the memtable, and a record left
+    /// incomplete by a crash is dropped.
+    pub fn open_with<P: AsRef<Path>>(dir: P, options: Options) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let manifest = if dir.join(manifest::FILE_NAME).exists() {
+            Manifest::load(&dir)?
+        } else {
+            let manifest = Manifest::new();
+            manifest.save(&dir)?;
+            manifest
+        };
+        let segments = manifest
+            .segments
+            .iter()
+            .map(|id| Segment::open(&dir, *id))
+            .collect::<io::Result<_>>()?;
+        let mut wal = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(dir.join(WAL_FILE_NAME))?;
+        let memtable = replay(&mut wal)?;
+
+        let mut map = VMapLsm {
+            dir,
+            options,
+            manifest,
+            segments,
+            memtable,
+            wal,
+            cache: Frozen::new(),
+            _phantom_data: PhantomData,
+        };
+        if map.memtable.len() >= map.options.memtable_entries {
+            map.flush()?;
+        }
+        Ok(map)
+    }
+
+    pub fn stats(&self) -> io::Result<Stats> {
+        let mut segment_bytes = 0;
+        for segment in &self.segments {
+            segment_bytes += segment.len()?;
+        }
+        Ok(Stats {
+            memtable_entries: self.memtable.len(),
+            segments: self.segments.len(),
+            segment_bytes,
+        })
+    }
+
+    /// Ranges `(lo, hi]` of the versions of the records to look for,
+    /// in order, for the state at `version`: its own, and those of the
+    /// states its rollbacks link to.
+    fn ranges(&self, mut version: u64) -> Vec<(u64, u64)> {
+        let links = &self.manifest.links;
+        let mut ranges = vec![];
+        loop {
+            match links.partition_point(|(this, _)| *this <= version) {
+                0 => {
+                    ranges.push((0, version));
+                    return ranges;
+                }
+                i => {
+                    let (this, linked_to) = links[i - 1];
+                    ranges.push((this, version));
+                    version = linked_to;
+                }
+            }
+        }
+    }
+
+    fn lookup(&self, key: &[u8], version: u64) -> io::Result<Found<'_, V>> {
+        for (lo, hi) in self.ranges(version) {
+            if lo >= hi {
+                continue;
+            }
+            let bounds = (key.to_vec(), lo + 1)..=(key.to_vec(), hi);
+            let mut found = self
+                .memtable
+                .range(bounds)
+                .next_back()
+                .map(|((_, version), v)| (*version, Found::Memtable(v.as_ref())));
+            // the newest segment wins on records of the same version
+            for segment in self.segments.iter().rev() {
+                if let Some((version, v)) = segment.find(key, lo, hi)? {
+                    if found.as_ref().is_none_or(|(newest, _)| version > *newest) {
+                        found = Some((version, Found::Segment(v)));
+                    }
+                }
+            }
+            if let Some((_, found)) = found {
+                return Ok(found);
+            }
+        }
+        Ok(Found::Memtable(None))
+    }
+
+    fn get_bytes(&self, key: &[u8]) -> io::Result<Option<&V>> {
+        if let Some(v) = self.cache.get(key) {
+            return Ok(Some(v));
+        }
+        match self.lookup(key, self.manifest.current_ver)? {
+            Found::Memtable(v) => Ok(v),
+            Found::Segment(None) => Ok(None),
+            Found::Segment(Some(bytes)) => Ok(Some(self.cache.insert(key.to_vec(), read(&bytes)?))),
+        }
+    }
+
+    fn get_owned(&self, key: &[u8], version: u64) -> io::Result<Option<V>>
+    where
+        V: Clone,
+    {
+        match self.lookup(key, version)? {
+            Found::Memtable(v) => Ok(v.cloned()),
+            Found::Segment(v) => v.map(|bytes| read(&bytes)).transpose(),
+        }
+    }
+
+    fn write(&mut self, key: Vec<u8>, v: Option<V>) -> io::Result<()> {
+        self.cache.forget(&key);
+        self.append(key, self.manifest.current_ver, v)?;
+        self.flush_if_full()
+    }
+
+    /// Writes to the log and the memtable only, never flushing.
+    fn append(&mut self, key: Vec<u8>, version: u64, v: Option<V>) -> io::Result<()> {
+        let mut record = vec![];
+        segment::write_record(&mut record, &key, version, v.as_ref().map(V::as_bytes))?;
+        self.wal.write_all(&record)?;
+        self.memtable.insert((key, version), v);
+        Ok(())
+    }
+
+    fn flush_if_full(&mut self) -> io::Result<()> {
+        if self.memtable.len() >= self.options.memtable_entries {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_memtable(&mut self) -> io::Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        let id = self.manifest.next_segment;
+        let mut writer = segment::Writer::create(&self.dir, id)?;
+        for ((key, version), v) in &self.memtable {
+            writer.push(key, *version, v.as_ref().map(V::as_bytes))?;
+        }
+        self.segments.extend(writer.finish()?);
+        self.manifest.segments = self.segments.iter().map(|segment| segment.id).collect();
+        self.manifest.next_segment += 1;
+        self.manifest.save(&self.dir)?;
+        self.wal.set_len(0)?;
+        self.memtable.clear();
+        self.cache.clear();
+        Ok(())
+    }
+
+    /// Writes the memtable to a new segment, and compacts the segments if
+    /// there are too many.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.write_memtable()?;
+        if self.segments.len() > self.options.max_segments {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Merges all segments and the memtable into one segment, dropping
+    /// the records neither the live state nor any tagged state reads.
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.write_memtable()?;
+        if self.segments.is_empty() {
+            return Ok(());
+        }
+        let versions =
+            std::iter::once(&self.manifest.current_ver).chain(self.manifest.snapshots.values());
+        let retained: Vec<Vec<(u64, u64)>> = versions.map(|v| self.ranges(*v)).collect();
+
+        let id = self.manifest.next_segment;
+        let mut writer = segment::Writer::create(&self.dir, id)?;
+        let mut records: Vec<Record> = vec![];
+        for record in Merged::new(&self.segments)? {
+            let record = record?;
+            if records.first().is_some_and(|(key, _, _)| *key != record.0) {
+                keep_read(&records, &retained, &mut writer)?;
+                records.clear();
+            }
+            records.push(record);
+        }
+        keep_read(&records, &retained, &mut writer)?;
+
+        let compacted: Vec<Segment> = writer.finish()?.into_iter().collect();
+        let old = mem::replace(&mut self.segments, compacted);
+        self.manifest.segments = self.segments.iter().map(|segment| segment.id).collect();
+        self.manifest.next_segment += 1;
+        self.manifest.save(&self.dir)?;
+        for segment in old {
+            segment.remove()?;
+        }
+        self.cache.clear();
+        Ok(())
+    }
+
+    fn save_manifest(&mut self) -> io::Result<()> {
+        // the log holds writes of versions the manifest is about to
+        // declare past
+        self.wal.sync_data()?;
+        self.manifest.save(&self.dir)
+    }
+
+    fn checkpoint_at(&mut self, tag: String) -> io::Result<()> {
+        let version = self.manifest.current_ver;
+        self.manifest.snapshots.insert(tag, version);
+        self.manifest.current_ver += 1;
+        self.save_manifest()
+    }
+
+    /// Tags a version of its own holding the current state with `ops`
+    /// written over it, which the current state links past before any
+    /// of them is. Nothing is flushed until the tag is saved, as
+    /// compaction would drop the records of a version no tag reads.
+    fn checkpoint_with_at(
+        &mut self,
+        tag: String,
+        ops: Vec<(Vec<u8>, Option<V>)>,
+    ) -> io::Result<()> {
+        let live = self.manifest.current_ver;
+        self.manifest.current_ver += 1;
+        let version = self.manifest.current_ver;
+        self.link_to(live)?;
+        for (key, v) in ops {
+            self.append(key, version, v)?;
+        }
+        self.manifest.snapshots.insert(tag, version);
+        self.save_manifest()?;
+        self.flush_if_full()
+    }
+
+    fn link_to(&mut self, found: u64) -> io::Result<()> {
+        self.manifest.current_ver += 1;
+        let this = self.manifest.current_ver;
+        self.manifest.links.push((this, found));
+        self.manifest.current_ver += 1;
+        self.cache.clear();
+        self.save_manifest()
+    }
+
+    /// Keys of the live state and of all tagged states, sorted.
+    fn keys(&self) -> io::Result<Vec<Vec<u8>>> {
+        let mut keys: Vec<Vec<u8>> = vec![];
+        for record in Merged::new(&self.segments)? {
+            let (key, _, _) = record?;
+            if keys.last() != Some(&key) {
+                keys.push(key);
+            }
+        }
+        keys.extend(self.memtable.keys().map(|(key, _)| key.clone()));
+        keys.sort_unstable();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+/// Writes the records of one key, sorted by version, which any of the
+/// `retained` states reads, unless none of them has the key.
+fn keep_read(
+    records: &[Record],
+    retained: &[Vec<(u64, u64)>],
+    writer: &mut segment::Writer,
+) -> io::Result<()> {
+    let mut read: Vec<usize> = retained
+        .iter()
+        .filter_map(|ranges| {
+            ranges.iter().find_map(|(lo, hi)| {
+                let i = records.partition_point(|(_, version, _)| version <= hi);
+                (i > 0 && records[i - 1].1 > *lo).then(|| i - 1)
+            })
+        })
+        .collect();
+    if read.iter().all(|i| records[*i].2.is_none()) {
+        return Ok(());
+    }
+    read.sort_unstable();
+    read.dedup();
+    for i in read {
+        let (key, version, v) = &records[i];
+        writer.push(key, *version, v.as_deref())?;
+    }
+    Ok(())
+}
+
+fn replay<V: AsFromBytes>(wal: &mut File) -> io::Result<Memtable<V>> {
+    let len = wal.metadata()?.len();
+    wal.seek(SeekFrom::Start(0))?;
+    let mut input = BufReader::new(&*wal);
+    let mut memtable = BTreeMap::new();
+    let mut offset = 0;
+    while offset < len {
+        let (key, version, v) = match segment::read_record(&mut input) {
+            Ok(record) => record,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        offset += segment::record_len(&key, v.as_deref());
+        let v = v.map(|bytes| read(&bytes)).transpose()?;
+        memtable.insert((key, version), v);
+    }
+    drop(input);
+    if offset < len {
+        wal.set_len(offset)?;
+    }
+    Ok(memtable)
+}
+
+impl<K, V> crate::VersionedMap<K, V> for VMapLsm<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes + Clone,
+{
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        let key = k.as_bytes().to_vec();
+        let old = check(self.get_owned(&key, self.manifest.current_ver));
+        check(self.write(key, Some(v)));
+        old
+    }
+
+    fn get(&self, k: &K) -> Option<&V> {
+        check(self.get_bytes(k.as_bytes()))
+    }
+
+    fn remove(&mut self, k: &K) -> Option<V> {
+        let key = k.as_bytes().to_vec();
+        let old = check(self.get_owned(&key, self.manifest.current_ver));
+        if old.is_some() {
+            check(self.write(key, None));
+        }
+        old
+    }
+
+    fn checkpoint(&mut self, tag: String) {
+        check(self.checkpoint_at(tag));
+    }
+
+    fn rollback(&mut self, tag: String) -> bool {
+        match self.manifest.snapshots.get(&tag) {
+            Some(version) => {
+                check(self.link_to(*version));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String> {
+        let found = *self.manifest.snapshots.get(&tag)?;
+        let saved = save_as.unwrap_or_else(|| {
+            crate::autosave_tag(|tag| self.manifest.snapshots.contains_key(tag))
+        });
+        check(self.checkpoint_at(saved.clone()));
+        check(self.link_to(found));
+        Some(saved)
+    }
+
+    fn checkpoint_with(&mut self, tag: String, ops: Vec<(K, Option<V>)>) {
+        let ops = ops
+            .into_iter()
+            .map(|(k, v)| (k.as_bytes().to_vec(), v))
+            .collect();
+        check(self.checkpoint_with_at(tag, ops));
+    }
+
+    /// Also compacts, dropping the history no longer read.
+    fn prune(&mut self) {
+        self.manifest.snapshots.clear();
+        check(self.compact());
+        check(self.save_manifest());
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self.manifest.snapshots.keys().map(String::as_str).collect();
+        tags.sort_unstable();
+        tags
+    }
+
+    fn entries(&self) -> Vec<(K, &V)> {
+        let keys = check(self.keys());
+        let mut entries = vec![];
+        for key in keys {
+            if let Some(v) = check(self.get_bytes(&key)) {
+                entries.push((check(read(&key)), v));
+            }
+        }
+        entries
+    }
+}
+
+impl<K, V> crate::Diff<K, V> for VMapLsm<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes + Clone + PartialEq,
+{
+    fn diff_live(&self, tag: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let tagged = *self.manifest.snapshots.get(tag)?;
+        Some(check(self.diff_versions(self.manifest.current_ver, tagged)))
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let from = *self.manifest.snapshots.get(from)?;
+        let to = *self.manifest.snapshots.get(to)?;
+        Some(check(self.diff_versions(from, to)))
+    }
+}
+
+impl<K, V> VMapLsm<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes + Clone + PartialEq,
+{
+    fn diff_versions(&self, from: u64, to: u64) -> io::Result<Vec<crate::Change<K, V>>> {
+        let mut changes = vec![];
+        for key in self.keys()? {
+            let old = self.get_owned(&key, from)?;
+            let new = self.get_owned(&key, to)?;
+            changes.extend(crate::change(read(&key)?, old.as_ref(), new.as_ref()));
+        }
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use quickcheck::{QuickCheck, TestResult};
+
+    use super::{Options, VMapLsm, WAL_FILE_NAME};
+    use crate::test_helpers::model::Op;
+    use crate::treelike_no_trie::VMapNoTrie;
+    use crate::{Diff, VersionedMap};
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let count = COUNT.fetch_add(1, Ordering::Relaxed);
+            let dir = format!("tt-lsm-{}-{}", std::process::id(), count);
+            TempDir(std::env::temp_dir().join(dir))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    type Map = VMapLsm<String, String>;
+
+    // small enough for a few operations to flush and compact
+    const TINY: Options = Options {
+        memtable_entries: 3,
+        max_segments: 2,
+    };
+
+    fn contents(map: &dyn VersionedMap<String, String>) -> Vec<(String, String)> {
+        let mut entries: Vec<_> = map
+            .entries()
+            .into_iter()
+            .map(|(k, v)| (k, v.clone()))
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    #[test]
+    fn behaves_like_the_other_backends() {
+        fn property(ops: Vec<Op>) -> TestResult {
+            let dir = TempDir::new();
+            let mut map = Map::open_with(&dir.0, TINY).unwrap();
+            let mut reference = VMapNoTrie::<String, String>::new();
+            for (i, op) in ops.iter().enumerate() {
+                match op {
+                    Op::Insert(k, v) => assert_eq!(
+                        reference.insert(k.clone(), v.to_string()),
+                        map.insert(k.clone(), v.to_string())
+                    ),
+                    Op::Remove(k) => assert_eq!(reference.remove(k), map.remove(k)),
+                    Op::Checkpoint(tag) => {
+                        reference.checkpoint(tag.clone());
+                        map.checkpoint(tag.clone());
+                    }
+                    Op::Rollback(tag) => {
+                        assert_eq!(reference.rollback(tag.clone()), map.rollback(tag.clone()))
+                    }
+                    Op::Prune => {
+                        reference.prune();
+                        map.prune();
+                    }
+                }
+                if i % 7 == 6 {
+                    drop(map);
+                    map = Map::open_with(&dir.0, TINY).unwrap();
+                }
+                assert_eq!(contents(&reference), contents(&map));
+                assert_eq!(reference.tags(), map.tags());
+                for tag in reference.tags() {
+                    let mut expected = reference.diff_live(tag).unwrap();
+                    let mut actual = map.diff_live(tag).unwrap();
+                    expected.sort_unstable_by(|a, b| a.key().cmp(b.key()));
+                    actual.sort_unstable_by(|a, b| a.key().cmp(b.key()));
+                    assert_eq!(expected, actual);
+                }
+            }
+            TestResult::passed()
+        }
+        QuickCheck::new()
+            .tests(50)
+            .quickcheck(property as fn(Vec<Op>) -> TestResult);
+    }
+
+    #[test]
+    fn unflushed_writes_survive_reopening() {
+        let dir = TempDir::new();
+        let mut map = Map::open(&dir.0).unwrap();
+        map.insert("a".to_owned(), "1".to_owned());
+        map.checkpoint("ONE".to_owned());
+        map.insert("a".to_owned(), "2".to_owned());
+        map.insert("b".to_owned(), "3".to_owned());
+        assert_eq!(3, map.stats().unwrap().memtable_entries);
+        drop(map);
+
+        let mut wal = OpenOptions::new()
+            .append(true)
+            .open(dir.0.join(WAL_FILE_NAME))
+            .unwrap();
+        wal.write_all(&[1, 0, 0]).unwrap();
+        let mut map = Map::open(&dir.0).unwrap();
+        assert_eq!(Some(&"2".to_owned()), map.get(&"a".to_owned()));
+        assert_eq!(Some(&"3".to_owned()), map.get(&"b".to_owned()));
+        assert!(map.rollback("ONE".to_owned()));
+        assert_eq!(vec![("a".to_owned(), "1".to_owned())], contents(&map));
+
+        map.insert("c".to_owned(), "4".to_owned());
+        drop(map);
+        let map = Map::open(&dir.0).unwrap();
+        assert_eq!(Some(&"4".to_owned()), map.get(&"c".to_owned()));
+        assert_eq!(None, map.get(&"b".to_owned()));
+    }
+
+    #[test]
+    fn checkpoint_with_survives_reopening_and_compaction() {
+        let dir = TempDir::new();
+        let mut map = Map::open_with(&dir.0, TINY).unwrap();
+        map.insert("a".to_owned(), "1".to_owned());
+        map.insert("b".to_owned(), "2".to_owned());
+        map.checkpoint_with(
+            "ONE".to_owned(),
+            vec![
+                ("a".to_owned(), None),
+                ("c".to_owned(), Some("3".to_owned())),
+            ],
+        );
+        map.insert("b".to_owned(), "4".to_owned());
+        let live = vec![
+            ("a".to_owned(), "1".to_owned()),
+            ("b".to_owned(), "4".to_owned()),
+        ];
+        assert_eq!(live, contents(&map));
+        drop(map);
+
+        let mut map = Map::open_with(&dir.0, TINY).unwrap();
+        assert_eq!(live, contents(&map));
+        map.compact().unwrap();
+        assert_eq!(live, contents(&map));
+        assert!(map.rollback("ONE".to_owned()));
+        assert_eq!(
+            vec![
+                ("b".to_owned(), "2".to_owned()),
+                ("c".to_owned(), "3".to_owned()),
+            ],
+            contents(&map)
+        );
+    }
+
+    #[test]
+    fn checkpoint_with_more_ops_than_fit_in_memory() {
+        let dir = TempDir::new();
+        let mut map = Map::open_with(&dir.0, TINY).unwrap();
+        map.insert("a".to_owned(), "1".to_owned());
+        let n = TINY.memtable_entries * TINY.max_segments * 4;
+        let ops = (0..n).map(|i| (format!("k{}", i), Some(i.to_string())));
+        map.checkpoint_with("ONE".to_owned(), ops.collect());
+        assert_eq!(1, contents(&map).len());
+        map.insert("b".to_owned(), "2".to_owned());
+        map.compact().unwrap();
+
+        assert!(map.rollback("ONE".to_owned()));
+        assert_eq!(n + 1, contents(&map).len());
+        drop(map);
+        let mut map = Map::open_with(&dir.0, TINY).unwrap();
+        assert!(map.rollback("ONE".to_owned()));
+        assert_eq!(n + 1, contents(&map).len());
+    }
+
+    #[test]
+    fn compaction_keeps_tagged_states_only() {
+        let dir = TempDir::new();
+        let mut map = Map::open_with(
+            &dir.0,
+            Options {
+                max_segments: 100,
+                ..TINY
+            },
+        )
+        .unwrap();
+        for i in 0..100 {
+            map.insert("key".to_owned(), i.to_string());
+            let tag = if i == 10 { "TEN" } else { "LAST" };
+            map.checkpoint(tag.to_owned());
+        }
+        map.insert("gone".to_owned(), "soon".to_owned());
+        map.remove(&"gone".to_owned());
+        map.flush().unwrap();
+        let before = map.stats().unwrap();
+        assert!(before.segments > 30);
+
+        map.compact().unwrap();
+        let after = map.stats().unwrap();
+        assert_eq!(1, after.segments);
+        assert!(after.segment_bytes < before.segment_bytes / 10);
+        assert_eq!(Some(&"99".to_owned()), map.get(&"key".to_owned()));
+        assert_eq!(None, map.get(&"gone".to_owned()));
+        assert!(map.rollback("TEN".to_owned()));
+        assert_eq!(Some(&"10".to_owned()), map.get(&"key".to_owned()));
+        assert!(map.rollback("LAST".to_owned()));
+
+        map.prune();
+        assert!(!map.rollback("TEN".to_owned()));
+        assert_eq!(vec![("key".to_owned(), "99".to_owned())], contents(&map));
+        assert!(map.stats().unwrap().segment_bytes < after.segment_bytes);
+    }
+}
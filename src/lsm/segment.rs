@@ -0,0 +1,300 @@
+//! Immutable files of records sorted by key and version.
+//!
+//! After the header come the records, then a sparse index of every
+//! `INDEX_EVERY`th record with its offset, and a footer with the range
+//! of versions the records cover and the offset of the index.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::persist::{invalid, read_bytes, read_len, write_bytes, write_len};
+
+const MAGIC: &[u8; 4] = b"TTLS";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: u64 = 5;
+const FOOTER_LEN: u64 = 24;
+
+const INDEX_EVERY: usize = 16;
+
+/// Key bytes, version, and value bytes or `None` for a removal.
+pub(super) type Record = (Vec<u8>, u64, Option<Vec<u8>>);
+
+pub(super) fn write_record<W: Write>(
+    out: &mut W,
+    key: &[u8],
+    version: u64,
+    value: Option<&[u8]>,
+) -> io::Result<u64> {
+    write_bytes(out, key)?;
+    out.write_all(&version.to_le_bytes())?;
+    match value {
+        Some(v) => {
+            out.write_all(&[1])?;
+            write_bytes(out, v)?;
+        }
+        None => out.write_all(&[0])?,
+    }
+    Ok(record_len(key, value))
+}
+
+pub(super) fn read_record<R: Read>(input: &mut R) -> io::Result<Record> {
+    let key = read_bytes(input)?;
+    let version = read_len(input)?;
+    let mut flag = [0; 1];
+    input.read_exact(&mut flag)?;
+    let value = match flag[0] {
+        0 => None,
+        1 => Some(read_bytes(input)?),
+        _ => return Err(invalid("malformed record")),
+    };
+    Ok((key, version, value))
+}
+
+pub(super) fn record_len(key: &[u8], value: Option<&[u8]>) -> u64 {
+    (8 + key.len() + 8 + 1 + value.map_or(0, |v| 8 + v.len())) as u64
+}
+
+pub(super) struct Segment {
+    pub id: u64,
+    path: PathBuf,
+    file: File,
+    pub min_version: u64,
+    pub max_version: u64,
+    // key and version of every `INDEX_EVERY`th record, and its offset
+    index: Vec<(Vec<u8>, u64, u64)>,
+    data_end: u64,
+}
+
+pub(super) fn path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("segment-{:06}", id))
+}
+
+/// Writes a segment one record at a time, in order of key and version.
+pub(super) struct Writer {
+    dir: PathBuf,
+    id: u64,
+    tmp: PathBuf,
+    out: BufWriter<File>,
+    offset: u64,
+    count: usize,
+    // key and version of every `INDEX_EVERY`th record, and its offset
+    index: Vec<(Vec<u8>, u64, u64)>,
+    min_version: u64,
+    max_version: u64,
+}
+
+impl Writer {
+    pub fn create(dir: &Path, id: u64) -> io::Result<Writer> {
+        let tmp = path(dir, id).with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&[FORMAT_VERSION])?;
+        Ok(Writer {
+            dir: dir.to_path_buf(),
+            id,
+            tmp,
+            out,
+            offset: HEADER_LEN,
+            count: 0,
+            index: vec![],
+            min_version: u64::MAX,
+            max_version: 0,
+        })
+    }
+
+    pub fn push(&mut self, key: &[u8], version: u64, value: Option<&[u8]>) -> io::Result<()> {
+        if self.count % INDEX_EVERY == 0 {
+            self.index.push((key.to_vec(), version, self.offset));
+        }
+        self.count += 1;
+        self.min_version = self.min_version.min(version);
+        self.max_version = self.max_version.max(version);
+        self.offset += write_record(&mut self.out, key, version, value)?;
+        Ok(())
+    }
+
+    /// The segment written, `None` if no record was.
+    pub fn finish(mut self) -> io::Result<Option<Segment>> {
+        if self.count == 0 {
+            drop(self.out);
+            fs::remove_file(&self.tmp)?;
+            return Ok(None);
+        }
+        write_len(&mut self.out, self.index.len())?;
+        for (key, version, offset) in &self.index {
+            write_bytes(&mut self.out, key)?;
+            self.out.write_all(&version.to_le_bytes())?;
+            self.out.write_all(&offset.to_le_bytes())?;
+        }
+        self.out.write_all(&self.min_version.to_le_bytes())?;
+        self.out.write_all(&self.max_version.to_le_bytes())?;
+        self.out.write_all(&self.offset.to_le_bytes())?;
+        self.out
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(&self.tmp, path(&self.dir, self.id))?;
+        Segment::open(&self.dir, self.id).map(Some)
+    }
+}
+
+impl Segment {
+    pub fn open(dir: &Path, id: u64) -> io::Result<Segment> {
+        let path = path(dir, id);
+        let mut file = File::open(&path)?;
+        let mut header = [0; HEADER_LEN as usize];
+        file.read_exact(&mut header)?;
+        if &header[..4] != MAGIC || header[4] != FORMAT_VERSION {
+            return Err(invalid("not a segment"));
+        }
+        file.seek(SeekFrom::End(-(FOOTER_LEN as i64)))?;
+        let min_version = read_len(&mut file)?;
+        let max_version = read_len(&mut file)?;
+        let data_end = read_len(&mut file)?;
+
+        file.seek(SeekFrom::Start(data_end))?;
+        let mut input = BufReader::new(&file);
+        let mut index = vec![];
+        for _ in 0..read_len(&mut input)? {
+            let key = read_bytes(&mut input)?;
+            let version = read_len(&mut input)?;
+            let offset = read_len(&mut input)?;
+            index.push((key, version, offset));
+        }
+        drop(input);
+        Ok(Segment {
+            id,
+            path,
+            file,
+            min_version,
+            max_version,
+            index,
+            data_end,
+        })
+    }
+
+    pub fn remove(self) -> io::Result<()> {
+        fs::remove_file(&self.path)
+    }
+
+    pub fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// The record of `key` with the greatest version in `(lo, hi]`.
+    pub fn find(&self, key: &[u8], lo: u64, hi: u64) -> io::Result<Option<(u64, Option<Vec<u8>>)>> {
+        if self.max_version <= lo || self.min_version > hi {
+            return Ok(None);
+        }
+        let start = match self
+            .index
+            .partition_point(|(k, version, _)| (k.as_slice(), *version) <= (key, hi))
+        {
+            0 => HEADER_LEN,
+            i => self.index[i - 1].2,
+        };
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(start))?;
+        let mut input = BufReader::new(file);
+        let mut offset = start;
+        let mut found = None;
+        while offset < self.data_end {
+            let (k, version, value) = read_record(&mut input)?;
+            if (k.as_slice(), version) > (key, hi) {
+                break;
+            }
+            offset += record_len(&k, value.as_deref());
+            if k == key {
+                found = Some((version, value));
+            }
+        }
+        Ok(found.filter(|(version, _)| *version > lo))
+    }
+
+    /// All records, in order.
+    pub fn records(&self) -> io::Result<Records> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(HEADER_LEN))?;
+        Ok(Records {
+            input: BufReader::new(file),
+            offset: HEADER_LEN,
+            end: self.data_end,
+        })
+    }
+}
+
+pub(super) struct Records {
+    input: BufReader<File>,
+    offset: u64,
+    end: u64,
+}
+
+impl Iterator for Records {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.end {
+            return None;
+        }
+        let record = read_record(&mut self.input);
+        match &record {
+            Ok((key, _, value)) => self.offset += record_len(key, value.as_deref()),
+            Err(_) => self.offset = self.end,
+        }
+        Some(record)
+    }
+}
+
+/// Records of several segments merged in order, the newest segment's
+/// record winning when more have the same key and version.
+pub(super) struct Merged {
+    // oldest segment first
+    sources: Vec<Records>,
+    heads: Vec<Option<Record>>,
+}
+
+impl Merged {
+    pub fn new(segments: &[Segment]) -> io::Result<Merged> {
+        let mut sources = segments
+            .iter()
+            .map(Segment::records)
+            .collect::<io::Result<Vec<_>>>()?;
+        let heads = sources
+            .iter_mut()
+            .map(|records| records.next().transpose())
+            .collect::<io::Result<_>>()?;
+        Ok(Merged { sources, heads })
+    }
+}
+
+impl Iterator for Merged {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut newest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let (key, version, _) = match head {
+                Some(head) => head,
+                None => continue,
+            };
+            let smaller = match newest.and_then(|j| self.heads[j].as_ref()) {
+                Some((k, v, _)) => (key, version) <= (k, v),
+                None => true,
+            };
+            if smaller {
+                newest = Some(i);
+            }
+        }
+        let record = self.heads[newest?].clone().unwrap();
+        for (head, source) in self.heads.iter_mut().zip(&mut self.sources) {
+            if matches!(head, Some((k, v, _)) if *k == record.0 && *v == record.1) {
+                match source.next().transpose() {
+                    Ok(next) => *head = next,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+        }
+        Some(Ok(record))
+    }
+}
@@ -0,0 +1,202 @@
+//! Saving a map with all its snapshots to bytes and loading it back.
+//!
+//! The format holds the live entries in full, and every snapshot as the
+//! puts and deletes turning the live state into it, so that loading works
+//! the same into any backend.
+
+use std::io::{self, Read, Write};
+
+use crate::treelike::AsFromBytes;
+use crate::{Change, Diff, VersionedMap};
+
+const MAGIC: &[u8; 4] = b"TTVM";
+const FORMAT_VERSION: u8 = 1;
+
+const PUT: u8 = 0;
+const DEL: u8 = 1;
+
+/// Writes the live entries of `map` and all its snapshots to `out`.
+pub fn save<M, K, V, W>(map: &M, mut out: W) -> io::Result<()>
+where
+    M: VersionedMap<K, V> + Diff<K, V> + ?Sized,
+    K: AsFromBytes,
+    V: AsFromBytes + Clone,
+    W: Write,
+{
+    out.write_all(MAGIC)?;
+    out.write_all(&[FORMAT_VERSION])?;
+
+    let entries = map.entries();
+    write_len(&mut out, entries.len())?;
+    for (k, v) in &entries {
+        write_bytes(&mut out, k.as_bytes())?;
+        write_bytes(&mut out, v.as_bytes())?;
+    }
+
+    let tags = map.tags();
+    write_len(&mut out, tags.len())?;
+    for tag in tags {
+        let changes = map.diff_live(tag).expect("listed tags are known");
+        write_bytes(&mut out, tag.as_bytes())?;
+        write_len(&mut out, changes.len())?;
+        for change in changes {
+            match change {
+                Change::Inserted { key, value } | Change::Updated { key, new: value, .. } => {
+                    out.write_all(&[PUT])?;
+                    write_bytes(&mut out, key.as_bytes())?;
+                    write_bytes(&mut out, value.as_bytes())?;
+                }
+                Change::Removed { key, .. } => {
+                    out.write_all(&[DEL])?;
+                    write_bytes(&mut out, key.as_bytes())?;
+                }
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Reads what [`save`] wrote into `map`, which is expected to be empty.
+/// Malformed input is reported as [`io::ErrorKind::InvalidData`], and may
+/// leave `map` partially loaded.
+pub fn load<M, K, V, R>(map: &mut M, mut input: R) -> io::Result<()>
+where
+    M: VersionedMap<K, V> + ?Sized,
+    K: AsFromBytes + Clone,
+    V: AsFromBytes + Clone,
+    R: Read,
+{
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a saved versioned map"));
+    }
+    let mut version = [0; 1];
+    input.read_exact(&mut version)?;
+    if version[0] != FORMAT_VERSION {
+        return Err(invalid("unsupported format version"));
+    }
+
+    for _ in 0..read_len(&mut input)? {
+        let k = read_value(&mut input)?;
+        let v = read_value(&mut input)?;
+        map.insert(k, v);
+    }
+
+    for _ in 0..read_len(&mut input)? {
+        let tag = String::from_utf8(read_bytes(&mut input)?)
+            .map_err(|_| invalid("tag isn't valid utf-8"))?;
+        let mut ops = vec![];
+        for _ in 0..read_len(&mut input)? {
+            let mut op = [0; 1];
+            input.read_exact(&mut op)?;
+            let k = read_value(&mut input)?;
+            let v = match op[0] {
+                PUT => Some(read_value(&mut input)?),
+                DEL => None,
+                _ => return Err(invalid("unknown operation")),
+            };
+            ops.push((k, v));
+        }
+        map.checkpoint_with(tag, ops);
+    }
+    Ok(())
+}
+
+pub(crate) fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn write_len<W: Write>(out: &mut W, len: usize) -> io::Result<()> {
+    out.write_all(&(len as u64).to_le_bytes())
+}
+
+pub(crate) fn write_bytes<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    write_len(out, bytes.len())?;
+    out.write_all(bytes)
+}
+
+pub(crate) fn read_len<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut len = [0; 8];
+    input.read_exact(&mut len)?;
+    Ok(u64::from_le_bytes(len))
+}
+
+pub(crate) fn read_bytes<R: Read>(input: &mut R) -> io::Result<Vec<u8>> {
+    let len = read_len(input)?;
+    let mut bytes = vec![];
+    // not trusting `len` with an allocation up front
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+pub(crate) fn read_value<R: Read, T: AsFromBytes>(input: &mut R) -> io::Result<T> {
+    T::read_from(&read_bytes(input)?).ok_or_else(|| invalid("unreadable key or value"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, save};
+    use crate::treelike::VMapTree;
+    use crate::treelike_no_trie::VMapNoTrie;
+    use crate::trivial::VMapTriv;
+    use crate::VersionedMap;
+
+    fn filled() -> VMapTree<String, String> {
+        let mut map = VMapTree::new();
+        map.insert("a".to_owned(), "1".to_owned());
+        map.insert("ab".to_owned(), "2".to_owned());
+        map.checkpoint("ONE".to_owned());
+        map.insert("ab".to_owned(), "3".to_owned());
+        map.remove(&"a".to_owned());
+        map.insert("b".to_owned(), "4".to_owned());
+        map.checkpoint("TWO".to_owned());
+        map.insert("c".to_owned(), "5".to_owned());
+        map
+    }
+
+    fn state(map: &dyn VersionedMap<String, String>) -> Vec<(String, String)> {
+        let mut entries: Vec<_> = map
+            .entries()
+            .into_iter()
+            .map(|(k, v)| (k, v.clone()))
+            .collect();
+        entries.sort_unstable();
+        entries
+    }
+
+    fn roundtrip(loaded: &mut dyn VersionedMap<String, String>) {
+        let mut original = filled();
+        let mut bytes = vec![];
+        save(&original, &mut bytes).unwrap();
+        load(loaded, &bytes[..]).unwrap();
+
+        assert_eq!(original.tags(), loaded.tags());
+        assert_eq!(state(&original), state(loaded));
+        for tag in ["ONE", "TWO"] {
+            original.rollback(tag.to_owned());
+            loaded.rollback(tag.to_owned());
+            assert_eq!(state(&original), state(loaded));
+        }
+    }
+
+    #[test]
+    fn roundtrip_into_every_backend() {
+        roundtrip(&mut VMapTriv::new());
+        roundtrip(&mut VMapTree::new());
+        roundtrip(&mut VMapNoTrie::new());
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let mut bytes = vec![];
+        save(&filled(), &mut bytes).unwrap();
+
+        let mut map = VMapTriv::<String, String>::new();
+        assert!(load(&mut map, &b"nope"[..]).is_err());
+        assert!(load(&mut map, &bytes[..bytes.len() - 1]).is_err());
+    }
+}
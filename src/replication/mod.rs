@@ -0,0 +1,597 @@
+//! Keeping read replicas of a map, possibly of another backend, in step
+//! with it.
+//!
+//! A [`Primary`] wraps the map written to and logs every change made to
+//! it as a numbered [`Record`]. A [`Follower`] applies the records in
+//! order, starting either from an empty map and the first record, or from
+//! a [`Snapshot`] of the primary and the records after it.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use crate::persist::{self, read_len, read_value, write_bytes, write_len};
+use crate::treelike::AsFromBytes;
+use crate::{Diff, VersionedMap};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op<K, V> {
+    Insert(K, V),
+    Remove(K),
+    Checkpoint(String),
+    Rollback(String),
+    /// Carries the tag the state was saved under, generated or not, so
+    /// that followers save it under the same one.
+    RollbackPreserving {
+        tag: String,
+        saved: String,
+    },
+    Prune,
+    /// Sets every key of `ops` to its value, or removes it on `None`.
+    CheckpointWith {
+        tag: String,
+        ops: Vec<(K, Option<V>)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<K, V> {
+    /// Numbers start at 1 and have no gaps.
+    pub seq: u64,
+    pub op: Op<K, V>,
+}
+
+const INSERT: u8 = 0;
+const REMOVE: u8 = 1;
+const CHECKPOINT: u8 = 2;
+const ROLLBACK: u8 = 3;
+const ROLLBACK_PRESERVING: u8 = 4;
+const PRUNE: u8 = 5;
+const CHECKPOINT_WITH: u8 = 6;
+
+impl<K, V> Record<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes,
+{
+    /// Writes the record in a form [`Record::decode`] reads back.
+    pub fn encode<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(&self.seq.to_le_bytes())?;
+        match &self.op {
+            Op::Insert(k, v) => {
+                out.write_all(&[INSERT])?;
+                write_bytes(out, k.as_bytes())?;
+                write_bytes(out, v.as_bytes())
+            }
+            Op::Remove(k) => {
+                out.write_all(&[REMOVE])?;
+                write_bytes(out, k.as_bytes())
+            }
+            Op::Checkpoint(tag) => {
+                out.write_all(&[CHECKPOINT])?;
+                write_bytes(out, tag.as_bytes())
+            }
+            Op::Rollback(tag) => {
+                out.write_all(&[ROLLBACK])?;
+                write_bytes(out, tag.as_bytes())
+            }
+            Op::RollbackPreserving { tag, saved } => {
+                out.write_all(&[ROLLBACK_PRESERVING])?;
+                write_bytes(out, tag.as_bytes())?;
+                write_bytes(out, saved.as_bytes())
+            }
+            Op::Prune => out.write_all(&[PRUNE]),
+            Op::CheckpointWith { tag, ops } => {
+                out.write_all(&[CHECKPOINT_WITH])?;
+                write_bytes(out, tag.as_bytes())?;
+                write_len(out, ops.len())?;
+                for (k, v) in ops {
+                    match v {
+                        Some(v) => {
+                            out.write_all(&[INSERT])?;
+                            write_bytes(out, k.as_bytes())?;
+                            write_bytes(out, v.as_bytes())?;
+                        }
+                        None => {
+                            out.write_all(&[REMOVE])?;
+                            write_bytes(out, k.as_bytes())?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads a record, `None` if the input ends cleanly before it starts.
+    pub fn decode<R: Read>(input: &mut R) -> io::Result<Option<Self>> {
+        let mut seq = [0; 8];
+        // ending anywhere past the first byte cuts a record short
+        loop {
+            match input.read(&mut seq[..1]) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        input.read_exact(&mut seq[1..])?;
+        let mut kind = [0; 1];
+        input.read_exact(&mut kind)?;
+        let op = match kind[0] {
+            INSERT => Op::Insert(read_value(input)?, read_value(input)?),
+            REMOVE => Op::Remove(read_value(input)?),
+            CHECKPOINT => Op::Checkpoint(read_value(input)?),
+            ROLLBACK => Op::Rollback(read_value(input)?),
+            ROLLBACK_PRESERVING => Op::RollbackPreserving {
+                tag: read_value(input)?,
+                saved: read_value(input)?,
+            },
+            PRUNE => Op::Prune,
+            CHECKPOINT_WITH => {
+                let tag = read_value(input)?;
+                let mut ops = vec![];
+                for _ in 0..read_len(input)? {
+                    let mut kind = [0; 1];
+                    input.read_exact(&mut kind)?;
+                    let k = read_value(input)?;
+                    let v = match kind[0] {
+                        INSERT => Some(read_value(input)?),
+                        REMOVE => None,
+                        _ => return Err(persist::invalid("unknown operation")),
+                    };
+                    ops.push((k, v));
+                }
+                Op::CheckpointWith { tag, ops }
+            }
+            _ => return Err(persist::invalid("unknown operation")),
+        };
+        Ok(Some(Record {
+            seq: u64::from_le_bytes(seq),
+            op,
+        }))
+    }
+}
+
+/// Full state of a primary, in the [`persist`] format, as of record `seq`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub seq: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Wraps a [`VersionedMap`], logging the changes made through it.
+/// Operations which change nothing, such as removing an absent key or
+/// rolling back to an unknown tag, aren't logged.
+pub struct Primary<M, K, V> {
+    inner: M,
+    last_seq: u64,
+    log: VecDeque<Record<K, V>>,
+}
+
+impl<M, K, V> Primary<M, K, V> {
+    /// Followers of a primary made over a map which isn't empty have to
+    /// start from a [`Primary::snapshot`].
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            last_seq: 0,
+            log: VecDeque::new(),
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Number of the last record logged, 0 before the first.
+    pub fn last_seq(&self) -> u64 {
+        self.last_seq
+    }
+
+    /// Records after `seq`, `None` if some of them were truncated already,
+    /// in which case a follower at `seq` has to start over from a snapshot.
+    pub fn records_since(&self, seq: u64) -> Option<impl Iterator<Item = &Record<K, V>>> {
+        // none past the last one, nor any number to overflow
+        if seq >= self.last_seq {
+            return Some(self.log.iter().skip(self.log.len()));
+        }
+        let first = self
+            .log
+            .front()
+            .map_or(self.last_seq + 1, |record| record.seq);
+        if seq + 1 < first {
+            return None;
+        }
+        Some(self.log.iter().skip((seq + 1 - first) as usize))
+    }
+
+    /// Drops the records up to `seq` included, once every follower has them.
+    pub fn truncate_log(&mut self, seq: u64) {
+        while self.log.front().is_some_and(|record| record.seq <= seq) {
+            self.log.pop_front();
+        }
+    }
+
+    fn log(&mut self, op: Op<K, V>) {
+        self.last_seq += 1;
+        self.log.push_back(Record {
+            seq: self.last_seq,
+            op,
+        });
+    }
+}
+
+impl<M, K, V> Primary<M, K, V>
+where
+    M: VersionedMap<K, V> + Diff<K, V>,
+    K: AsFromBytes,
+    V: AsFromBytes + Clone,
+{
+    pub fn snapshot(&self) -> Snapshot {
+        let mut bytes = vec![];
+        persist::save(&self.inner, &mut bytes).expect("writing to memory doesn't fail");
+        Snapshot {
+            seq: self.last_seq,
+            bytes,
+        }
+    }
+}
+
+impl<M, K, V> VersionedMap<K, V> for Primary<M, K, V>
+where
+    M: VersionedMap<K, V>,
+    K: Clone,
+    V: Clone,
+{
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        self.log(Op::Insert(k.clone(), v.clone()));
+        self.inner.insert(k, v)
+    }
+
+    fn get(&self, k: &K) -> Option<&V> {
+        self.inner.get(k)
+    }
+
+    fn remove(&mut self, k: &K) -> Option<V> {
+        let old = self.inner.remove(k);
+        if old.is_some() {
+            self.log(Op::Remove(k.clone()));
+        }
+        old
+    }
+
+    fn checkpoint(&mut self, tag: String) {
+        self.log(Op::Checkpoint(tag.clone()));
+        self.inner.checkpoint(tag)
+    }
+
+    fn rollback(&mut self, tag: String) -> bool {
+        let done = self.inner.rollback(tag.clone());
+        if done {
+            self.log(Op::Rollback(tag));
+        }
+        done
+    }
+
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String> {
+        let saved = self.inner.rollback_preserving(tag.clone(), save_as)?;
+        self.log(Op::RollbackPreserving {
+            tag,
+            saved: saved.clone(),
+        });
+        Some(saved)
+    }
+
+    fn prune(&mut self) {
+        self.log(Op::Prune);
+        self.inner.prune()
+    }
+
+    fn checkpoint_with(&mut self, tag: String, ops: Vec<(K, Option<V>)>) {
+        self.log(Op::CheckpointWith {
+            tag: tag.clone(),
+            ops: ops.clone(),
+        });
+        self.inner.checkpoint_with(tag, ops)
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        self.inner.tags()
+    }
+
+    fn entries(&self) -> Vec<(K, &V)> {
+        self.inner.entries()
+    }
+}
+
+/// A record was missed between the last one applied and the one given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    pub expected: u64,
+    pub got: u64,
+}
+
+/// Read replica of a [`Primary`], kept up to date by applying its records.
+pub struct Follower<M> {
+    inner: M,
+    applied: u64,
+}
+
+impl<M> Follower<M> {
+    /// Follows a primary from its first record on, `inner` is expected to
+    /// be empty.
+    pub fn new(inner: M) -> Self {
+        Self { inner, applied: 0 }
+    }
+
+    /// Follows a primary from `snapshot` on, loading it into `inner`,
+    /// which is expected to be empty.
+    pub fn bootstrap<K, V>(mut inner: M, snapshot: &Snapshot) -> io::Result<Self>
+    where
+        M: VersionedMap<K, V>,
+        K: AsFromBytes + Clone,
+        V: AsFromBytes + Clone,
+    {
+        persist::load(&mut inner, &snapshot.bytes[..])?;
+        Ok(Self {
+            inner,
+            applied: snapshot.seq,
+        })
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Number of the last record applied.
+    pub fn applied(&self) -> u64 {
+        self.applied
+    }
+
+    /// Applies `record` if it's the next one, returns whether it did.
+    /// Records applied already are skipped, so that overlapping batches
+    /// can be fed in.
+    pub fn apply<K, V>(&mut self, record: Record<K, V>) -> Result<bool, Gap>
+    where
+        M: VersionedMap<K, V>,
+        K: Clone,
+        V: Clone,
+    {
+        if record.seq <= self.applied {
+            return Ok(false);
+        }
+        if record.seq != self.applied + 1 {
+            return Err(Gap {
+                expected: self.applied + 1,
+                got: record.seq,
+            });
+        }
+        match record.op {
+            Op::Insert(k, v) => {
+                self.inner.insert(k, v);
+            }
+            Op::Remove(k) => {
+                self.inner.remove(&k);
+            }
+            Op::Checkpoint(tag) => self.inner.checkpoint(tag),
+            Op::Rollback(tag) => {
+                self.inner.rollback(tag);
+            }
+            Op::RollbackPreserving { tag, saved } => {
+                self.inner.rollback_preserving(tag, Some(saved));
+            }
+            Op::Prune => self.inner.prune(),
+            Op::CheckpointWith { tag, ops } => self.inner.checkpoint_with(tag, ops),
+        }
+        self.applied = record.seq;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use quickcheck::{QuickCheck, TestResult};
+
+    use super::{Follower, Gap, Op, Primary, Record};
+    use crate::test_helpers::model;
+    use crate::treelike::VMapTree;
+    use crate::treelike_no_trie::VMapNoTrie;
+    use crate::trivial::VMapTriv;
+    use crate::{Diff, VersionedMap};
+
+    type Map = dyn VersionedMap<String, String>;
+
+    fn apply(map: &mut Map, op: &model::Op) {
+        match op {
+            model::Op::Insert(k, v) => {
+                map.insert(k.clone(), v.to_string());
+            }
+            model::Op::Remove(k) => {
+                map.remove(k);
+            }
+            model::Op::Checkpoint(tag) => map.checkpoint(tag.clone()),
+            model::Op::Rollback(tag) => {
+                map.rollback(tag.clone());
+            }
+            model::Op::Prune => map.prune(),
+        }
+    }
+
+    fn same_state<A, B>(a: &A, b: &B) -> bool
+    where
+        A: VersionedMap<String, String> + Diff<String, String>,
+        B: VersionedMap<String, String> + Diff<String, String>,
+    {
+        fn sorted(map: &dyn VersionedMap<String, String>) -> Vec<(String, String)> {
+            let mut entries: Vec<_> = map
+                .entries()
+                .into_iter()
+                .map(|(k, v)| (k, v.clone()))
+                .collect();
+            entries.sort_unstable();
+            entries
+        }
+        let diffs = |map: &dyn Diff<String, String>, tag: &str| {
+            let mut changes = map.diff_live(tag).unwrap();
+            changes.sort_by(|x, y| x.key().cmp(y.key()));
+            changes
+        };
+        sorted(a) == sorted(b)
+            && a.tags() == b.tags()
+            && a.tags().iter().all(|tag| diffs(a, tag) == diffs(b, tag))
+    }
+
+    #[test]
+    fn followers_of_other_backends_keep_up() {
+        fn property(ops: Vec<model::Op>, split: usize) -> TestResult {
+            let split = if ops.is_empty() { 0 } else { split % ops.len() };
+            let mut primary = Primary::new(VMapNoTrie::new());
+            let mut from_start = Follower::new(VMapTree::new());
+
+            for op in &ops[..split] {
+                apply(&mut primary, op);
+            }
+            let snapshot = primary.snapshot();
+            let mut from_snapshot = Follower::bootstrap(VMapTriv::new(), &snapshot).unwrap();
+            for op in &ops[split..] {
+                apply(&mut primary, op);
+            }
+
+            for record in primary.records_since(0).unwrap() {
+                assert_eq!(Ok(true), from_start.apply(record.clone()));
+            }
+            // the overlap with the snapshot is skipped
+            for record in primary
+                .records_since(snapshot.seq.saturating_sub(2))
+                .unwrap()
+            {
+                from_snapshot.apply(record.clone()).unwrap();
+            }
+
+            assert_eq!(primary.last_seq(), from_start.applied());
+            assert_eq!(primary.last_seq(), from_snapshot.applied());
+            TestResult::from_bool(
+                same_state(primary.inner(), from_start.inner())
+                    && same_state(primary.inner(), from_snapshot.inner()),
+            )
+        }
+        QuickCheck::new()
+            .tests(300)
+            .quickcheck(property as fn(Vec<model::Op>, usize) -> TestResult);
+    }
+
+    #[test]
+    fn rollback_preserving_replicates_saved_tag() {
+        let mut primary = Primary::new(VMapTree::new());
+        let mut follower = Follower::new(VMapNoTrie::new());
+        primary.checkpoint("EMPTY".to_owned());
+        primary.insert("a".to_owned(), "1".to_owned());
+        let saved = primary
+            .rollback_preserving("EMPTY".to_owned(), None)
+            .unwrap();
+        assert_eq!(
+            None,
+            primary.rollback_preserving("UNKNOWN".to_owned(), None)
+        );
+
+        for record in primary.records_since(0).unwrap() {
+            follower.apply(record.clone()).unwrap();
+        }
+        assert_eq!(vec!["EMPTY", saved.as_str()], follower.inner().tags());
+        assert!(same_state(primary.inner(), follower.inner()));
+    }
+
+    #[test]
+    fn checkpoint_with_is_one_record() {
+        let mut primary = Primary::new(VMapNoTrie::new());
+        let mut follower = Follower::new(VMapTree::new());
+        primary.insert("a".to_owned(), "1".to_owned());
+        primary.checkpoint_with(
+            "ONE".to_owned(),
+            vec![
+                ("a".to_owned(), None),
+                ("b".to_owned(), Some("2".to_owned())),
+            ],
+        );
+
+        let records: Vec<Record<String, String>> =
+            primary.records_since(0).unwrap().cloned().collect();
+        assert_eq!(2, records.len());
+        let mut bytes = vec![];
+        records[1].encode(&mut bytes).unwrap();
+        assert_eq!(Some(&records[1]), Record::decode(&mut &bytes[..]).unwrap().as_ref());
+
+        for record in records {
+            follower.apply(record).unwrap();
+        }
+        assert!(same_state(primary.inner(), follower.inner()));
+        assert!(follower.inner.rollback("ONE".to_owned()));
+        assert_eq!(vec![("b".to_owned(), &"2".to_owned())], follower.inner().entries());
+    }
+
+    #[test]
+    fn records_roundtrip_and_gaps_are_refused() {
+        let mut primary = Primary::new(VMapTriv::new());
+        primary.insert("a".to_owned(), "1".to_owned());
+        primary.remove(&"b".to_owned());
+        primary.remove(&"a".to_owned());
+        primary.checkpoint("ONE".to_owned());
+        primary.rollback("ONE".to_owned());
+        primary.rollback_preserving("ONE".to_owned(), Some("TWO".to_owned()));
+        primary.prune();
+
+        let records: Vec<Record<String, String>> =
+            primary.records_since(0).unwrap().cloned().collect();
+        // removing an absent key isn't logged
+        assert_eq!(6, records.len());
+        assert_eq!(Op::Remove("a".to_owned()), records[1].op);
+
+        let mut bytes = vec![];
+        for record in &records {
+            record.encode(&mut bytes).unwrap();
+        }
+        let mut input = &bytes[..];
+        let mut decoded = vec![];
+        while let Some(record) = Record::decode(&mut input).unwrap() {
+            decoded.push(record);
+        }
+        assert_eq!(records, decoded);
+
+        let first_len = {
+            let mut first = vec![];
+            records[0].encode(&mut first).unwrap();
+            first.len()
+        };
+        for cut in first_len + 1..first_len + 8 {
+            let mut input = &bytes[..cut];
+            assert_eq!(Some(&records[0]), Record::decode(&mut input).unwrap().as_ref());
+            let err = Record::<String, String>::decode(&mut input).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind(), "cut at {}", cut);
+        }
+
+        let mut follower = Follower::new(VMapTree::<String, String>::new());
+        assert_eq!(
+            Err(Gap {
+                expected: 1,
+                got: 2
+            }),
+            follower.apply(records[1].clone())
+        );
+
+        primary.truncate_log(3);
+        assert!(primary.records_since(2).is_none());
+        assert_eq!(
+            Some(4),
+            primary.records_since(3).unwrap().next().map(|r| r.seq)
+        );
+        assert_eq!(0, primary.records_since(6).unwrap().count());
+        assert_eq!(0, primary.records_since(7).unwrap().count());
+        assert_eq!(0, primary.records_since(u64::MAX).unwrap().count());
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+
+use super::resp::{self, Value};
+use crate::treelike::AsFromBytes;
+use crate::Change;
+
+/// Connection to a [`Server`](super::Server). Errors the server replies
+/// with come back as [`io::ErrorKind::Other`].
+pub struct Client<K, V> {
+    input: Box<dyn BufRead + Send>,
+    output: Box<dyn Write + Send>,
+    _phantom_data: PhantomData<(K, V)>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn unexpected(reply: Value) -> io::Error {
+    invalid(&format!("unexpected reply {:?}", reply))
+}
+
+fn read<T: AsFromBytes>(bytes: &[u8]) -> io::Result<T> {
+    T::read_from(bytes).ok_or_else(|| invalid("unreadable key or value"))
+}
+
+impl<K, V> Client<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes,
+{
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let input = stream.try_clone()?;
+        Ok(Client::new(input, stream))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        let input = stream.try_clone()?;
+        Ok(Client::new(input, stream))
+    }
+
+    fn new<R, W>(input: R, output: W) -> Self
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        Client {
+            input: Box::new(BufReader::new(input)),
+            output: Box::new(output),
+            _phantom_data: PhantomData,
+        }
+    }
+
+    fn call(&mut self, args: &[&[u8]]) -> io::Result<Value> {
+        let request = Value::Array(args.iter().map(|arg| Value::Bulk(arg.to_vec())).collect());
+        let mut bytes = vec![];
+        resp::write_value(&mut bytes, &request)?;
+        self.output.write_all(&bytes)?;
+        self.output.flush()?;
+        match resp::read_value(&mut self.input)? {
+            Some(Value::Error(msg)) => Err(io::Error::other(msg)),
+            Some(reply) => Ok(reply),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn value_or_nil(reply: Value) -> io::Result<Option<V>> {
+        match reply {
+            Value::Nil => Ok(None),
+            Value::Bulk(bytes) => read(&bytes).map(Some),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    fn ok(reply: Value) -> io::Result<()> {
+        match reply {
+            Value::Ok => Ok(()),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn get(&mut self, k: &K) -> io::Result<Option<V>> {
+        let reply = self.call(&[b"GET", k.as_bytes()])?;
+        Self::value_or_nil(reply)
+    }
+
+    /// Returns the value replaced.
+    pub fn set(&mut self, k: &K, v: &V) -> io::Result<Option<V>> {
+        let reply = self.call(&[b"SET", k.as_bytes(), v.as_bytes()])?;
+        Self::value_or_nil(reply)
+    }
+
+    /// Returns the value removed.
+    pub fn del(&mut self, k: &K) -> io::Result<Option<V>> {
+        let reply = self.call(&[b"DEL", k.as_bytes()])?;
+        Self::value_or_nil(reply)
+    }
+
+    pub fn checkpoint(&mut self, tag: &str) -> io::Result<()> {
+        let reply = self.call(&[b"CHECKPOINT", tag.as_bytes()])?;
+        Self::ok(reply)
+    }
+
+    pub fn rollback(&mut self, tag: &str) -> io::Result<bool> {
+        match self.call(&[b"ROLLBACK", tag.as_bytes()])? {
+            Value::Int(n) => Ok(n != 0),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    pub fn prune(&mut self) -> io::Result<()> {
+        let reply = self.call(&[b"PRUNE"])?;
+        Self::ok(reply)
+    }
+
+    pub fn tags(&mut self) -> io::Result<Vec<String>> {
+        match self.call(&[b"TAGS"])? {
+            Value::Array(items) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::Bulk(bytes) => read(&bytes),
+                    item => Err(unexpected(item)),
+                })
+                .collect(),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Changes between the states tagged `from` and `to`, `None` if either
+    /// tag is unknown.
+    pub fn diff(&mut self, from: &str, to: &str) -> io::Result<Option<Vec<Change<K, V>>>> {
+        match self.call(&[b"DIFF", from.as_bytes(), to.as_bytes()])? {
+            Value::Nil => Ok(None),
+            Value::Array(items) => items
+                .into_iter()
+                .map(read_change)
+                .collect::<io::Result<_>>()
+                .map(Some),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+fn read_change<K: AsFromBytes, V: AsFromBytes>(item: Value) -> io::Result<Change<K, V>> {
+    let parts: Vec<Vec<u8>> = match item {
+        Value::Array(parts) => parts
+            .into_iter()
+            .map(|part| match part {
+                Value::Bulk(bytes) => Ok(bytes),
+                part => Err(unexpected(part)),
+            })
+            .collect::<io::Result<_>>()?,
+        item => return Err(unexpected(item)),
+    };
+    let parts: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+    let change = match parts[..] {
+        [b"inserted", key, value] => Change::Inserted {
+            key: read(key)?,
+            value: read(value)?,
+        },
+        [b"removed", key, old] => Change::Removed {
+            key: read(key)?,
+            old: read(old)?,
+        },
+        [b"updated", key, old, new] => Change::Updated {
+            key: read(key)?,
+            old: read(old)?,
+            new: read(new)?,
+        },
+        _ => return Err(invalid("malformed change")),
+    };
+    Ok(change)
+}
@@ -0,0 +1,471 @@
+//! Sharing one map between processes over TCP or a Unix socket.
+//!
+//! The map lives on a thread of its own, so that backends which aren't
+//! `Send` can be served too; connections hand it their requests in turn.
+//! Commands, with keys, values and tags as bulk strings:
+//!
+//! - `GET key`, `SET key value` and `DEL key` reply the value, the
+//!   replaced one or the removed one, or nil
+//! - `CHECKPOINT tag` and `PRUNE` reply `+OK`
+//! - `ROLLBACK tag` replies `:1`, or `:0` if `tag` is unknown
+//! - `TAGS` replies the tags
+//! - `DIFF from to` replies an array of `[inserted key value]`,
+//!   `[removed key old]` and `[updated key old new]`, nil if a tag is unknown
+//!
+//! Each connection has a thread of its own, up to [`MAX_CONNECTIONS`] at
+//! once; any more are sent an error and closed.
+
+mod client;
+pub mod resp;
+
+pub use client::Client;
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::treelike::AsFromBytes;
+use crate::{Change, Diff, VersionedMap};
+use resp::Value;
+
+/// Connections served at once.
+pub const MAX_CONNECTIONS: usize = 128;
+
+struct Request {
+    args: Vec<Vec<u8>>,
+    reply: Sender<Value>,
+}
+
+enum Endpoint {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// A running server, stopped by [`Server::shutdown`] or by being dropped.
+pub struct Server {
+    endpoint: Endpoint,
+    stop: Arc<AtomicBool>,
+    acceptor: Option<JoinHandle<()>>,
+}
+
+impl Server {
+    /// Serves the map made by `factory` on `addr`, port 0 picks a free one.
+    pub fn bind_tcp<A, F, M, K, V>(addr: A, factory: F) -> io::Result<Server>
+    where
+        A: ToSocketAddrs,
+        F: FnOnce() -> M + Send + 'static,
+        M: VersionedMap<K, V> + Diff<K, V>,
+        K: AsFromBytes + Clone,
+        V: AsFromBytes + Clone,
+    {
+        let listener = TcpListener::bind(addr)?;
+        let endpoint = Endpoint::Tcp(listener.local_addr()?);
+        Ok(Server::start(endpoint, factory, move || {
+            listener.accept().map(|(stream, _)| stream)
+        }))
+    }
+
+    /// Serves the map made by `factory` on a socket created at `path`.
+    #[cfg(unix)]
+    pub fn bind_unix<P, F, M, K, V>(path: P, factory: F) -> io::Result<Server>
+    where
+        P: AsRef<Path>,
+        F: FnOnce() -> M + Send + 'static,
+        M: VersionedMap<K, V> + Diff<K, V>,
+        K: AsFromBytes + Clone,
+        V: AsFromBytes + Clone,
+    {
+        let listener = UnixListener::bind(&path)?;
+        let endpoint = Endpoint::Unix(path.as_ref().to_owned());
+        Ok(Server::start(endpoint, factory, move || {
+            listener.accept().map(|(stream, _)| stream)
+        }))
+    }
+
+    /// Address clients connect to, `None` when serving on a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self.endpoint {
+            Endpoint::Tcp(addr) => Some(addr),
+            #[cfg(unix)]
+            Endpoint::Unix(_) => None,
+        }
+    }
+
+    fn start<F, M, K, V, S, A>(endpoint: Endpoint, factory: F, mut accept: A) -> Server
+    where
+        F: FnOnce() -> M + Send + 'static,
+        M: VersionedMap<K, V> + Diff<K, V>,
+        K: AsFromBytes + Clone,
+        V: AsFromBytes + Clone,
+        S: Send + 'static,
+        for<'a> &'a S: Read + Write,
+        A: FnMut() -> io::Result<S> + Send + 'static,
+    {
+        let (requests, incoming) = mpsc::channel::<Request>();
+        thread::spawn(move || {
+            let mut map = factory();
+            for request in incoming {
+                let reply = execute(&mut map, &request.args);
+                let _ = request.reply.send(reply);
+            }
+        });
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let open = Arc::new(AtomicUsize::new(0));
+        let acceptor = thread::spawn(move || loop {
+            let stream = accept();
+            if stopped.load(Ordering::SeqCst) {
+                break;
+            }
+            if let Ok(stream) = stream {
+                if open.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                    let _ = resp::write_value(&mut &stream, &error("too many connections"));
+                    continue;
+                }
+                open.fetch_add(1, Ordering::SeqCst);
+                let slot = Slot(open.clone());
+                let requests = requests.clone();
+                thread::spawn(move || {
+                    let _slot = slot;
+                    let _ = serve_connection(stream, requests);
+                });
+            }
+        });
+        Server {
+            endpoint,
+            stop,
+            acceptor: Some(acceptor),
+        }
+    }
+
+    /// Stops accepting connections. The map is dropped once the clients
+    /// connected already disconnect.
+    pub fn shutdown(mut self) {
+        self.stop_accepting();
+    }
+
+    fn stop_accepting(&mut self) {
+        let acceptor = match self.acceptor.take() {
+            Some(acceptor) => acceptor,
+            None => return,
+        };
+        self.stop.store(true, Ordering::SeqCst);
+        // the acceptor is blocked until someone connects
+        let _ = match &self.endpoint {
+            Endpoint::Tcp(addr) => TcpStream::connect(addr).map(drop),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => UnixStream::connect(path).map(drop),
+        };
+        let _ = acceptor.join();
+        #[cfg(unix)]
+        if let Endpoint::Unix(path) = &self.endpoint {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop_accepting();
+    }
+}
+
+/// Frees the place of a connection once its thread ends, however it ends.
+struct Slot(Arc<AtomicUsize>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn serve_connection<S>(stream: S, requests: Sender<Request>) -> io::Result<()>
+where
+    for<'a> &'a S: Read + Write,
+{
+    let mut input = BufReader::new(&stream);
+    let mut output = BufWriter::new(&stream);
+    while let Some(value) = resp::read_request(&mut input)? {
+        let reply = match value {
+            Value::Array(items) => match bulk_strings(items) {
+                Some(args) => {
+                    let (reply, replied) = mpsc::channel();
+                    if requests.send(Request { args, reply }).is_err() {
+                        return Err(io::ErrorKind::BrokenPipe.into());
+                    }
+                    replied.recv().unwrap_or_else(|_| error("map is gone"))
+                }
+                None => error("arguments must be bulk strings"),
+            },
+            _ => error("requests must be arrays"),
+        };
+        resp::write_value(&mut output, &reply)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+fn bulk_strings(items: Vec<Value>) -> Option<Vec<Vec<u8>>> {
+    items
+        .into_iter()
+        .map(|item| match item {
+            Value::Bulk(bytes) => Some(bytes),
+            _ => None,
+        })
+        .collect()
+}
+
+fn error(msg: &str) -> Value {
+    Value::Error(format!("ERR {}", msg))
+}
+
+fn key<K: AsFromBytes>(bytes: &[u8]) -> Result<K, Value> {
+    if bytes.is_empty() {
+        return Err(error("keys can't be empty"));
+    }
+    K::read_from(bytes).ok_or_else(|| error("unreadable key"))
+}
+
+fn value<V: AsFromBytes>(bytes: &[u8]) -> Result<V, Value> {
+    V::read_from(bytes).ok_or_else(|| error("unreadable value"))
+}
+
+fn tag(bytes: &[u8]) -> Result<String, Value> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| error("tags must be utf-8"))
+}
+
+fn bulk<T: AsFromBytes>(item: &T) -> Value {
+    Value::Bulk(item.as_bytes().to_vec())
+}
+
+fn bulk_or_nil<V: AsFromBytes>(v: Option<&V>) -> Value {
+    v.map_or(Value::Nil, bulk)
+}
+
+fn execute<M, K, V>(map: &mut M, args: &[Vec<u8>]) -> Value
+where
+    M: VersionedMap<K, V> + Diff<K, V>,
+    K: AsFromBytes + Clone,
+    V: AsFromBytes + Clone,
+{
+    match try_execute(map, args) {
+        Ok(reply) | Err(reply) => reply,
+    }
+}
+
+fn try_execute<M, K, V>(map: &mut M, args: &[Vec<u8>]) -> Result<Value, Value>
+where
+    M: VersionedMap<K, V> + Diff<K, V>,
+    K: AsFromBytes + Clone,
+    V: AsFromBytes + Clone,
+{
+    let (name, args) = args.split_first().ok_or_else(|| error("empty request"))?;
+    let name = String::from_utf8_lossy(name).to_ascii_uppercase();
+    let reply = match (name.as_str(), args) {
+        ("GET", [k]) => bulk_or_nil(map.get(&key(k)?)),
+        ("SET", [k, v]) => bulk_or_nil(map.insert(key(k)?, value(v)?).as_ref()),
+        ("DEL", [k]) => bulk_or_nil(map.remove(&key(k)?).as_ref()),
+        ("CHECKPOINT", [t]) => {
+            map.checkpoint(tag(t)?);
+            Value::Ok
+        }
+        ("ROLLBACK", [t]) => Value::Int(map.rollback(tag(t)?) as i64),
+        ("PRUNE", []) => {
+            map.prune();
+            Value::Ok
+        }
+        ("TAGS", []) => Value::Array(
+            map.tags()
+                .into_iter()
+                .map(|tag| Value::Bulk(tag.as_bytes().to_vec()))
+                .collect(),
+        ),
+        ("DIFF", [from, to]) => match map.diff(&tag(from)?, &tag(to)?) {
+            Some(changes) => Value::Array(changes.iter().map(change_value).collect()),
+            None => Value::Nil,
+        },
+        ("GET" | "SET" | "DEL" | "CHECKPOINT" | "ROLLBACK" | "PRUNE" | "TAGS" | "DIFF", _) => {
+            return Err(error(&format!("wrong number of arguments for {}", name)))
+        }
+        _ => return Err(error(&format!("unknown command {}", name))),
+    };
+    Ok(reply)
+}
+
+fn change_value<K: AsFromBytes, V: AsFromBytes>(change: &Change<K, V>) -> Value {
+    let label = |s: &str| Value::Bulk(s.as_bytes().to_vec());
+    Value::Array(match change {
+        Change::Inserted { key, value } => vec![label("inserted"), bulk(key), bulk(value)],
+        Change::Removed { key, old } => vec![label("removed"), bulk(key), bulk(old)],
+        Change::Updated { key, old, new } => {
+            vec![label("updated"), bulk(key), bulk(old), bulk(new)]
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Write};
+    use std::net::TcpStream;
+
+    use super::resp::{self, Value};
+    use super::{Client, Server, MAX_CONNECTIONS};
+    use crate::treelike::VMapTree;
+    use crate::Change;
+
+    fn tree() -> VMapTree<String, String> {
+        VMapTree::new()
+    }
+
+    #[test]
+    fn clients_share_the_map() {
+        let server = Server::bind_tcp("127.0.0.1:0", tree).unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut first = Client::<String, String>::connect_tcp(addr).unwrap();
+        let mut second = Client::<String, String>::connect_tcp(addr).unwrap();
+
+        assert_eq!(None, first.set(&"a".to_owned(), &"1".to_owned()).unwrap());
+        second.checkpoint("ONE").unwrap();
+        assert_eq!(
+            Some("1".to_owned()),
+            second.set(&"a".to_owned(), &"2".to_owned()).unwrap()
+        );
+        first.set(&"b".to_owned(), &"3".to_owned()).unwrap();
+        second.checkpoint("TWO").unwrap();
+
+        assert_eq!(vec!["ONE", "TWO"], first.tags().unwrap());
+        let mut changes = first.diff("ONE", "TWO").unwrap().unwrap();
+        changes.sort_by(|a, b| a.key().cmp(b.key()));
+        assert_eq!(
+            vec![
+                Change::Updated {
+                    key: "a".to_owned(),
+                    old: "1".to_owned(),
+                    new: "2".to_owned(),
+                },
+                Change::Inserted {
+                    key: "b".to_owned(),
+                    value: "3".to_owned(),
+                },
+            ],
+            changes
+        );
+        assert_eq!(None, first.diff("ONE", "UNKNOWN").unwrap());
+
+        assert!(first.rollback("ONE").unwrap());
+        assert!(!first.rollback("UNKNOWN").unwrap());
+        assert_eq!(Some("1".to_owned()), second.get(&"a".to_owned()).unwrap());
+        assert_eq!(Some("1".to_owned()), second.del(&"a".to_owned()).unwrap());
+        assert_eq!(None, first.get(&"b".to_owned()).unwrap());
+        second.prune().unwrap();
+        assert!(first.tags().unwrap().is_empty());
+
+        server.shutdown();
+    }
+
+    #[test]
+    fn bad_requests_get_errors() {
+        let server = Server::bind_tcp("127.0.0.1:0", tree).unwrap();
+        let mut client =
+            Client::<String, String>::connect_tcp(server.local_addr().unwrap()).unwrap();
+        assert!(client.get(&String::new()).is_err());
+
+        let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let mut replies = BufReader::new(stream.try_clone().unwrap());
+        let request = |args: &[&str]| {
+            let items = args
+                .iter()
+                .map(|arg| Value::Bulk(arg.as_bytes().to_vec()))
+                .collect();
+            let mut bytes = vec![];
+            resp::write_value(&mut bytes, &Value::Array(items)).unwrap();
+            bytes
+        };
+        for (args, expected) in [
+            (&["NOPE"][..], "ERR unknown command NOPE"),
+            (&["get"][..], "ERR wrong number of arguments for GET"),
+        ] {
+            stream.write_all(&request(args)).unwrap();
+            assert_eq!(
+                Some(Value::Error(expected.to_owned())),
+                resp::read_value(&mut replies).unwrap()
+            );
+        }
+        stream.write_all(b":1\r\n").unwrap();
+        assert_eq!(
+            Some(Value::Error("ERR requests must be arrays".to_owned())),
+            resp::read_value(&mut replies).unwrap()
+        );
+        // still usable after errors
+        assert_eq!(None, client.get(&"a".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn deeply_nested_requests_drop_only_their_connection() {
+        let server = Server::bind_tcp("127.0.0.1:0", tree).unwrap();
+        let mut client =
+            Client::<String, String>::connect_tcp(server.local_addr().unwrap()).unwrap();
+        client.set(&"a".to_owned(), &"1".to_owned()).unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let mut replies = BufReader::new(stream.try_clone().unwrap());
+        // the server may hang up before all of it is written
+        let _ = stream.write_all(&b"*1\r\n".repeat(200_000));
+        assert!(!matches!(resp::read_value(&mut replies), Ok(Some(_))));
+
+        assert_eq!(Some("1".to_owned()), client.get(&"a".to_owned()).unwrap());
+    }
+
+    #[test]
+    fn connections_past_the_limit_are_refused() {
+        let server = Server::bind_tcp("127.0.0.1:0", tree).unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut clients: Vec<Client<String, String>> = (0..MAX_CONNECTIONS)
+            .map(|_| Client::connect_tcp(addr).unwrap())
+            .collect();
+
+        let refused = TcpStream::connect(addr).unwrap();
+        let mut replies = BufReader::new(refused);
+        assert_eq!(
+            Some(Value::Error("ERR too many connections".to_owned())),
+            resp::read_value(&mut replies).unwrap()
+        );
+        assert_eq!(None, resp::read_value(&mut replies).unwrap());
+        clients[0].set(&"a".to_owned(), &"1".to_owned()).unwrap();
+
+        // the place is freed once the server notices the hang-up
+        clients.pop();
+        let mut client = loop {
+            let mut client = Client::<String, String>::connect_tcp(addr).unwrap();
+            if client.get(&"a".to_owned()).is_ok() {
+                break client;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(Some("1".to_owned()), client.get(&"a".to_owned()).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serves_on_unix_socket() {
+        let path = std::env::temp_dir().join(format!("tt-server-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let server = Server::bind_unix(&path, tree).unwrap();
+
+        let mut client = Client::<String, String>::connect_unix(&path).unwrap();
+        client.set(&"a".to_owned(), &"1".to_owned()).unwrap();
+        assert_eq!(Some("1".to_owned()), client.get(&"a".to_owned()).unwrap());
+
+        server.shutdown();
+        assert!(!path.exists());
+    }
+}
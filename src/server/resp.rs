@@ -0,0 +1,164 @@
+//! Framing of requests and replies, a subset of the RESP protocol:
+//! requests are arrays of bulk strings, replies any of the values below.
+
+use std::io::{self, BufRead, Read, Write};
+
+// same as Redis, so that a corrupt length can't take all the memory
+const MAX_BULK_LEN: u64 = 512 * 1024 * 1024;
+const MAX_LINE_LEN: u64 = 64;
+// replies hold arrays of arrays at most; nesting is limited so that
+// arrays in arrays can't take all the stack
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Ok,
+    Nil,
+    Int(i64),
+    Bulk(Vec<u8>),
+    Array(Vec<Value>),
+    Error(String),
+}
+
+pub fn write_value<W: Write>(out: &mut W, value: &Value) -> io::Result<()> {
+    match value {
+        Value::Ok => out.write_all(b"+OK\r\n"),
+        Value::Nil => out.write_all(b"$-1\r\n"),
+        Value::Int(n) => write!(out, ":{}\r\n", n),
+        Value::Bulk(bytes) => {
+            write!(out, "${}\r\n", bytes.len())?;
+            out.write_all(bytes)?;
+            out.write_all(b"\r\n")
+        }
+        Value::Array(items) => {
+            write!(out, "*{}\r\n", items.len())?;
+            items.iter().try_for_each(|item| write_value(out, item))
+        }
+        // a line can't hold line breaks
+        Value::Error(msg) => write!(out, "-{}\r\n", msg.replace(['\r', '\n'], " ")),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The line without its `\r\n`, `None` if the input ended before it.
+fn read_line<R: BufRead>(input: &mut R, limit: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut line = vec![];
+    input.take(limit + 2).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if !line.ends_with(b"\r\n") {
+        return Err(invalid("line not terminated by CRLF"));
+    }
+    line.truncate(line.len() - 2);
+    Ok(Some(line))
+}
+
+fn parse_num(bytes: &[u8]) -> io::Result<i64> {
+    std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("malformed number"))
+}
+
+/// Reads one value, `None` if the input ends cleanly before it starts.
+pub fn read_value<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    read_nested(input, MAX_DEPTH)
+}
+
+/// Same as [`read_value`], refusing arrays inside arrays, which requests
+/// never hold.
+pub fn read_request<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    read_nested(input, 1)
+}
+
+/// Reads one value with arrays nested at most `depth` deep.
+fn read_nested<R: BufRead>(input: &mut R, depth: usize) -> io::Result<Option<Value>> {
+    let line = match read_line(input, MAX_LINE_LEN)? {
+        Some(line) => line,
+        None => return Ok(None),
+    };
+    let (kind, rest) = line.split_first().ok_or_else(|| invalid("empty line"))?;
+    let value = match kind {
+        b'+' if rest == b"OK" => Value::Ok,
+        b'-' => Value::Error(String::from_utf8_lossy(rest).into_owned()),
+        b':' => Value::Int(parse_num(rest)?),
+        b'$' => match parse_num(rest)? {
+            -1 => Value::Nil,
+            len if len < 0 || len as u64 > MAX_BULK_LEN => {
+                return Err(invalid("bulk length out of range"))
+            }
+            len => {
+                let mut bytes = vec![];
+                input.take(len as u64 + 2).read_to_end(&mut bytes)?;
+                if bytes.len() as i64 != len + 2 || !bytes.ends_with(b"\r\n") {
+                    return Err(invalid("truncated bulk string"));
+                }
+                bytes.truncate(len as usize);
+                Value::Bulk(bytes)
+            }
+        },
+        b'*' if depth == 0 => return Err(invalid("arrays nested too deep")),
+        b'*' => {
+            let len = parse_num(rest)?;
+            if len < 0 {
+                return Err(invalid("negative array length"));
+            }
+            let mut items = Vec::with_capacity(len.min(1024) as usize);
+            for _ in 0..len {
+                let item =
+                    read_nested(input, depth - 1)?.ok_or_else(|| invalid("truncated array"))?;
+                items.push(item);
+            }
+            Value::Array(items)
+        }
+        _ => return Err(invalid("unknown value type")),
+    };
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_request, read_value, write_value, Value};
+
+    #[test]
+    fn values_roundtrip() {
+        let value = Value::Array(vec![
+            Value::Ok,
+            Value::Nil,
+            Value::Int(-3),
+            Value::Bulk(b"with\r\nbreaks".to_vec()),
+            Value::Array(vec![]),
+            Value::Error("ERR oops".to_owned()),
+        ]);
+        let mut bytes = vec![];
+        write_value(&mut bytes, &value).unwrap();
+
+        let mut input = &bytes[..];
+        assert_eq!(Some(value), read_value(&mut input).unwrap());
+        assert_eq!(None, read_value(&mut input).unwrap());
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        for bytes in [&b"$5\r\nab\r\n"[..], b"*2\r\n:1\r\n", b"?\r\n", b":1\n"] {
+            assert!(read_value(&mut &bytes[..]).is_err(), "{:?}", bytes);
+        }
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let bytes = b"*1\r\n".repeat(200_000);
+        assert!(read_value(&mut &bytes[..]).is_err());
+        assert!(read_request(&mut &bytes[..]).is_err());
+
+        let nested = b"*1\r\n*1\r\n$1\r\na\r\n";
+        assert!(read_value(&mut &nested[..]).unwrap().is_some());
+        assert!(read_request(&mut &nested[..]).is_err());
+        let flat = b"*2\r\n$1\r\na\r\n$0\r\n\r\n";
+        assert!(read_request(&mut &flat[..]).unwrap().is_some());
+    }
+}
@@ -1,5 +1,8 @@
 macro_rules! versioned_map_trait_tests {
     ($impl_name:ident) => {
+        versioned_map_trait_tests!($impl_name, new);
+    };
+    ($impl_name:ident, $new:ident) => {
         #[cfg(test)]
         mod tests {
             use std::collections::HashSet;
@@ -15,7 +18,7 @@ macro_rules! versioned_map_trait_tests {
                 fn property(keys: HashSet<String>) -> TestResult {
                     let entries = attach_values(cartesian_product(keys));
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -38,7 +41,7 @@ macro_rules! versioned_map_trait_tests {
                 fn property(keys: HashSet<String>) -> TestResult {
                     let entries = attach_values(cartesian_product(keys));
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -65,7 +68,7 @@ macro_rules! versioned_map_trait_tests {
                     let entries1 = attach_values(keys.clone());
                     let entries2 = attach_values(keys);
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -99,7 +102,7 @@ macro_rules! versioned_map_trait_tests {
                         .map(attach_values)
                         .collect();
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -154,7 +157,7 @@ macro_rules! versioned_map_trait_tests {
                         .map(attach_values)
                         .collect();
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -226,7 +229,7 @@ macro_rules! versioned_map_trait_tests {
                         .map(attach_values)
                         .collect();
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -260,12 +263,247 @@ macro_rules! versioned_map_trait_tests {
                 );
             }
 
+            #[test]
+            fn checkpoint_with_leaves_live_state() {
+                fn property(
+                    keys_live: HashSet<String>,
+                    keys_tagged: HashSet<String>,
+                ) -> TestResult {
+                    let live = attach_values(cartesian_product(keys_live));
+                    let tagged = attach_values(cartesian_product(keys_tagged));
+
+                    let hashmap = $impl_name::$new();
+                    let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
+                        Box::new(hashmap);
+
+                    for (key, value) in live.clone() {
+                        under_test.insert(key, value);
+                    }
+                    // every other live key removed, every tagged one set
+                    let removed = live.iter().step_by(2).map(|(k, _)| (k.clone(), None));
+                    let set = tagged.iter().map(|(k, v)| (k.clone(), Some(!v)));
+                    let ops: Vec<(String, Option<u32>)> = removed.chain(set).collect();
+                    under_test.checkpoint_with("ONE".to_owned(), ops.clone());
+
+                    for (k, v) in &live {
+                        assert_eq!(Some(v), under_test.get(k));
+                    }
+                    let mut expected: std::collections::HashMap<String, u32> =
+                        live.into_iter().collect();
+                    for (k, v) in ops {
+                        match v {
+                            Some(v) => expected.insert(k, v),
+                            None => expected.remove(&k),
+                        };
+                    }
+
+                    assert!(under_test.rollback("ONE".to_owned()));
+                    let mut entries: Vec<(String, u32)> = under_test
+                        .entries()
+                        .into_iter()
+                        .map(|(k, v)| (k, *v))
+                        .collect();
+                    entries.sort();
+                    let mut expected: Vec<(String, u32)> = expected.into_iter().collect();
+                    expected.sort();
+                    assert_eq!(expected, entries);
+
+                    TestResult::passed()
+                }
+                QuickCheck::new().quickcheck(
+                    property as fn(HashSet<String>, HashSet<String>) -> TestResult,
+                );
+            }
+
+            #[test]
+            fn entry_and_update() {
+                fn property(keys: HashSet<String>) -> TestResult {
+                    use crate::VersionedMap;
+
+                    let entries = attach_values(cartesian_product(keys));
+
+                    let mut under_test = $impl_name::<String, u32>::$new();
+
+                    for (key, value) in entries.clone() {
+                        assert_eq!(&value, under_test.entry(key).or_insert(value));
+                    }
+                    under_test.checkpoint("ONE".to_owned());
+
+                    for (key, value) in &entries {
+                        assert!(under_test.update(key, |v| *v = v.wrapping_add(1)));
+                        under_test
+                            .entry(key.clone())
+                            .and_modify(|v| *v = v.wrapping_add(1))
+                            .or_insert(0);
+                        assert_eq!(Some(&value.wrapping_add(2)), under_test.get(key));
+                    }
+
+                    for (key, value) in &entries {
+                        under_test.remove(key);
+                        assert!(!under_test.update(key, |v| *v = 0));
+                        under_test.entry(key.clone()).and_modify(|v| *v = 0);
+                        assert_eq!(None, under_test.get(key));
+                        under_test.entry(key.clone()).or_insert_with(|| *value);
+                    }
+
+                    assert!(under_test.rollback("ONE".to_owned()));
+                    for (key, value) in &entries {
+                        assert_eq!(Some(value), under_test.get(key));
+                    }
+
+                    TestResult::passed()
+                }
+                QuickCheck::new()
+                    .quickcheck(property as fn(HashSet<String>) -> TestResult);
+            }
+
+            #[test]
+            fn collect_and_extend() {
+                fn property(keys: HashSet<String>) -> TestResult {
+                    use crate::VersionedMap;
+
+                    let entries = attach_values(cartesian_product(keys));
+
+                    let mut under_test: $impl_name<String, u32> =
+                        entries.iter().cloned().collect();
+                    under_test.checkpoint("ONE".to_owned());
+                    under_test.extend(entries.iter().map(|(k, v)| (k.clone(), !v)));
+                    for (key, value) in &entries {
+                        assert_eq!(Some(&!value), under_test.get(key));
+                    }
+
+                    assert!(under_test.rollback("ONE".to_owned()));
+                    for (key, value) in &entries {
+                        assert_eq!(Some(value), under_test.get(key));
+                    }
+
+                    TestResult::passed()
+                }
+                QuickCheck::new()
+                    .quickcheck(property as fn(HashSet<String>) -> TestResult);
+            }
+
+            #[test]
+            fn standard_traits() {
+                use crate::VersionedMap;
+
+                let mut map: $impl_name<String, u32> = Default::default();
+                map.insert("a".to_owned(), 1);
+                map.insert("b".to_owned(), 2);
+                map.checkpoint("ONE".to_owned());
+
+                let mut copy = map.clone();
+                assert_eq!(map, copy);
+                copy.insert("a".to_owned(), 3);
+                assert_ne!(map, copy);
+                assert_eq!(1, map[&"a".to_owned()]);
+                assert_eq!(3, copy[&"a".to_owned()]);
+
+                // only the live entries are compared
+                copy.insert("a".to_owned(), 1);
+                copy.prune();
+                assert_eq!(map, copy);
+                copy.remove(&"b".to_owned());
+                assert_ne!(map, copy);
+                assert_ne!(copy, map);
+
+                let debug = format!("{:?}", map);
+                assert!(debug.contains("\"a\": 1"), "{}", debug);
+                assert!(debug.contains("tags: [\"ONE\"]"), "{}", debug);
+
+                let mut entries: Vec<(String, u32)> = map.into_iter().collect();
+                entries.sort_unstable();
+                assert_eq!(vec![("a".to_owned(), 1), ("b".to_owned(), 2)], entries);
+            }
+
+            #[test]
+            #[should_panic(expected = "no entry found for key")]
+            fn index_of_missing_key() {
+                let map = $impl_name::<String, u32>::$new();
+                let _ = map[&"a".to_owned()];
+            }
+
+            #[test]
+            fn model_based_ops() {
+                use crate::test_helpers::model::{check_against_model, Op};
+
+                fn property(ops: Vec<Op>) -> TestResult {
+                    let hashmap = $impl_name::$new();
+                    let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
+                        Box::new(hashmap);
+
+                    check_against_model(&mut *under_test, &ops)
+                }
+                QuickCheck::new()
+                    .tests(500)
+                    .quickcheck(property as fn(Vec<Op>) -> TestResult);
+            }
+
+            #[test]
+            fn rollback_preserving_current_state() {
+                fn property(keys_one: HashSet<String>) -> TestResult {
+                    let entries = attach_values(cartesian_product(keys_one));
+
+                    let hashmap = $impl_name::$new();
+                    let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
+                        Box::new(hashmap);
+
+                    under_test.checkpoint("EMPTY".to_owned());
+                    for (key, value) in entries.clone() {
+                        under_test.insert(key, value);
+                    }
+
+                    assert_eq!(
+                        None,
+                        under_test.rollback_preserving(
+                            "MISSING".to_owned(),
+                            Some("SAVED".to_owned())
+                        )
+                    );
+                    assert!(!under_test.rollback("SAVED".to_owned()));
+
+                    let saved = under_test
+                        .rollback_preserving("EMPTY".to_owned(), None)
+                        .unwrap();
+                    for (k, _v) in &entries {
+                        assert_eq!(None, under_test.get(k));
+                    }
+                    let saved_again = under_test
+                        .rollback_preserving("EMPTY".to_owned(), None)
+                        .unwrap();
+                    assert_ne!(saved, saved_again);
+
+                    assert!(under_test.rollback(saved));
+                    for (k, v) in &entries {
+                        assert_eq!(Some(v), under_test.get(k));
+                    }
+
+                    // the target is resolved before the current state is saved
+                    assert_eq!(
+                        Some("EMPTY".to_owned()),
+                        under_test
+                            .rollback_preserving("EMPTY".to_owned(), Some("EMPTY".to_owned()))
+                    );
+                    for (k, _v) in &entries {
+                        assert_eq!(None, under_test.get(k));
+                    }
+                    assert!(under_test.rollback("EMPTY".to_owned()));
+                    for (k, v) in &entries {
+                        assert_eq!(Some(v), under_test.get(k));
+                    }
+
+                    TestResult::passed()
+                }
+                QuickCheck::new()
+                    .quickcheck(property as fn(HashSet<String>) -> TestResult);
+            }
+
             #[test]
             fn snapshots_prune() {
                 fn property(keys_one: HashSet<String>) -> TestResult {
                     let entries_one = attach_values(cartesian_product(keys_one));
 
-                    let hashmap = $impl_name::new();
+                    let hashmap = $impl_name::$new();
                     let mut under_test: Box<dyn crate::VersionedMap<String, u32>> =
                         Box::new(hashmap);
 
@@ -2,6 +2,7 @@ use rand::{thread_rng, Rng};
 use std::collections::HashSet;
 
 pub mod common_tests;
+pub mod model;
 
 fn common_prefixes() -> Vec<String> {
     vec![
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use quickcheck::{Arbitrary, Gen, TestResult};
+
+// few short keys sharing prefixes, so that operations collide on keys
+// and on trie paths
+const KEYS: [&str; 8] = ["a", "b", "aa", "ab", "ba", "aab", "aba", "abb"];
+// the last one is never checkpointed, rollbacks to it must fail
+const TAGS: [&str; 4] = ["ONE", "TWO", "THREE", "UNKNOWN"];
+
+#[derive(Clone, Debug)]
+pub enum Op {
+    Insert(String, u32),
+    Remove(String),
+    Checkpoint(String),
+    Rollback(String),
+    Prune,
+}
+
+impl Arbitrary for Op {
+    fn arbitrary(g: &mut Gen) -> Self {
+        let key = |g: &mut Gen| g.choose(&KEYS).unwrap().to_string();
+        let tag = |g: &mut Gen| g.choose(&TAGS[..TAGS.len() - 1]).unwrap().to_string();
+        match u8::arbitrary(g) % 20 {
+            0..=8 => Op::Insert(key(g), u32::arbitrary(g) % 8),
+            9..=12 => Op::Remove(key(g)),
+            13..=15 => Op::Checkpoint(tag(g)),
+            16..=17 => Op::Rollback(tag(g)),
+            18 => Op::Rollback(TAGS[TAGS.len() - 1].to_owned()),
+            _ => Op::Prune,
+        }
+    }
+}
+
+/// Reference behaviour of a versioned map: a full copy of the live state
+/// per tag.
+#[derive(Default)]
+struct Model {
+    live: HashMap<String, u32>,
+    snapshots: HashMap<String, HashMap<String, u32>>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Value(Option<u32>),
+    RolledBack(bool),
+    Done,
+}
+
+impl Model {
+    fn apply(&mut self, op: &Op) -> Outcome {
+        match op {
+            Op::Insert(k, v) => Outcome::Value(self.live.insert(k.clone(), *v)),
+            Op::Remove(k) => Outcome::Value(self.live.remove(k)),
+            Op::Checkpoint(tag) => {
+                self.snapshots.insert(tag.clone(), self.live.clone());
+                Outcome::Done
+            }
+            Op::Rollback(tag) => match self.snapshots.get(tag) {
+                Some(snapshot) => {
+                    self.live = snapshot.clone();
+                    Outcome::RolledBack(true)
+                }
+                None => Outcome::RolledBack(false),
+            },
+            Op::Prune => {
+                self.snapshots.clear();
+                Outcome::Done
+            }
+        }
+    }
+}
+
+fn apply(map: &mut dyn crate::VersionedMap<String, u32>, op: &Op) -> Outcome {
+    match op {
+        Op::Insert(k, v) => Outcome::Value(map.insert(k.clone(), *v)),
+        Op::Remove(k) => Outcome::Value(map.remove(k)),
+        Op::Checkpoint(tag) => {
+            map.checkpoint(tag.clone());
+            Outcome::Done
+        }
+        Op::Rollback(tag) => Outcome::RolledBack(map.rollback(tag.clone())),
+        Op::Prune => {
+            map.prune();
+            Outcome::Done
+        }
+    }
+}
+
+/// Runs `ops` against `map` and the model in lockstep, comparing the return
+/// value of every operation and the whole visible state after it.
+pub fn check_against_model(
+    map: &mut dyn crate::VersionedMap<String, u32>,
+    ops: &[Op],
+) -> TestResult {
+    let mut model = Model::default();
+    for (step, op) in ops.iter().enumerate() {
+        let expected = model.apply(op);
+        let actual = apply(map, op);
+        if expected != actual {
+            return TestResult::error(format!(
+                "step {} {:?}: expected {:?}, got {:?}",
+                step, op, expected, actual
+            ));
+        }
+        let mut expected: Vec<(String, u32)> =
+            model.live.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        expected.sort_unstable();
+        let mut actual: Vec<(String, u32)> =
+            map.entries().into_iter().map(|(k, v)| (k, *v)).collect();
+        actual.sort_unstable();
+        if expected != actual {
+            return TestResult::error(format!(
+                "after step {} {:?}: expected entries {:?}, got {:?}",
+                step, op, expected, actual
+            ));
+        }
+        let mut expected: Vec<&str> = model.snapshots.keys().map(String::as_str).collect();
+        expected.sort_unstable();
+        if expected != map.tags() {
+            return TestResult::error(format!(
+                "after step {} {:?}: expected tags {:?}, got {:?}",
+                step, op, expected, map.tags()
+            ));
+        }
+        for key in KEYS {
+            let key = key.to_owned();
+            let expected = model.live.get(&key);
+            let actual = map.get(&key);
+            if expected != actual {
+                return TestResult::error(format!(
+                    "after step {} {:?}: expected {:?} under {:?}, got {:?}",
+                    step, op, expected, key, actual
+                ));
+            }
+        }
+    }
+    TestResult::passed()
+}
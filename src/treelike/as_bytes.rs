@@ -11,3 +11,12 @@ impl AsFromBytes for String {
         String::from_utf8(bytes.to_vec()).ok()
     }
 }
+
+impl AsFromBytes for Vec<u8> {
+    fn as_bytes(&self) -> &[u8] {
+        self
+    }
+    fn read_from(bytes: &[u8]) -> Option<Self> {
+        Some(bytes.to_vec())
+    }
+}
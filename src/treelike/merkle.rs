@@ -0,0 +1,96 @@
+//! Content hashes of tries, and proofs that a key holds a value, or
+//! nothing, checked against the root hash alone.
+//!
+//! A node is hashed from its value's hash, if any, and the byte and hash
+//! of each of its branches, so tries holding the same keys and values
+//! have the same root hash however they got there.
+
+use sha2::{Digest, Sha256};
+
+use super::as_bytes::AsFromBytes;
+use crate::Hash;
+
+pub(super) fn value_hash(bytes: &[u8]) -> Hash {
+    Sha256::digest(bytes).into()
+}
+
+pub(super) fn node_hash<'a, I>(terminal: Option<&Hash>, branches: I) -> Hash
+where
+    I: IntoIterator<Item = (u8, &'a Hash)>,
+{
+    let mut hasher = Sha256::new();
+    match terminal {
+        Some(hash) => {
+            hasher.update([1]);
+            hasher.update(hash);
+        }
+        None => hasher.update([0]),
+    }
+    for (b, hash) in branches {
+        hasher.update([b]);
+        hasher.update(hash);
+    }
+    hasher.finalize().into()
+}
+
+/// One node on the path to the proven key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Level {
+    pub terminal: Option<Hash>,
+    // all but the branch continuing the path, by byte
+    pub branches: Vec<(u8, Hash)>,
+}
+
+/// Proof that a key of a [`VMapTree`](super::VMapTree) held a value, or
+/// was missing, obtained with [`VMapTree::prove`](super::VMapTree::prove).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    // from the root down to the node of the key, or to the deepest node
+    // on its path
+    pub(super) levels: Vec<Level>,
+}
+
+impl Proof {
+    /// Whether `k` holds `v`, or is missing for `None`, in the trie with
+    /// the root hash `root`.
+    pub fn verify<K, V>(&self, root: &Hash, k: &K, v: Option<&V>) -> bool
+    where
+        K: AsFromBytes,
+        V: AsFromBytes,
+    {
+        let key = k.as_bytes();
+        let (last, above) = match self.levels.split_last() {
+            Some(split) => split,
+            None => return false,
+        };
+        if self.levels.len() > key.len() + 1 {
+            return false;
+        }
+        if self.levels.len() == key.len() + 1 {
+            if last.terminal != v.map(|v| value_hash(v.as_bytes())) {
+                return false;
+            }
+        } else {
+            // the path stops short of the key, which can't hold anything
+            let next = key[above.len()];
+            if v.is_some() || last.branches.iter().any(|(b, _)| *b == next) {
+                return false;
+            }
+        }
+
+        let mut hash = node_hash(
+            last.terminal.as_ref(),
+            last.branches.iter().map(|(b, h)| (*b, h)),
+        );
+        for (level, b) in above.iter().zip(key).rev() {
+            let mut branches: Vec<(u8, &Hash)> =
+                level.branches.iter().map(|(b, h)| (*b, h)).collect();
+            match branches.binary_search_by_key(b, |(b, _)| *b) {
+                Ok(_) => return false,
+                Err(i) => branches.insert(i, (*b, &hash)),
+            }
+            hash = node_hash(level.terminal.as_ref(), branches);
+        }
+        hash == *root
+    }
+}
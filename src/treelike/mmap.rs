@@ -0,0 +1,531 @@
+//! A [`VMapTree`] whose snapshots live in a file rather than in memory.
+//!
+//! Checkpoints append the nodes of the live trie not already in the file,
+//! found by their Merkle hash, and then the tag. Snapshots are read from
+//! a memory map of the file, only the nodes on the path of a key being
+//! touched, and values come straight out of the map. Only the live trie is
+//! kept in memory, rolling back reads a whole snapshot into it.
+//!
+//! Records, after the header:
+//! - node: `0`, its hash, `1` and the value or `0`, the number of branches
+//!   as `u16`, and each branch's byte and offset, children before parents
+//! - tag: `1`, the tag, the offset of the root node
+//!
+//! with lengths and offsets as `u64` little-endian.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use memmap2::{Mmap, MmapOptions};
+
+use super::as_bytes::AsFromBytes;
+use super::node::Node;
+use super::{bytes, VMapTree};
+use crate::persist::invalid;
+use crate::Hash;
+
+const MAGIC: &[u8; 4] = b"TTMM";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: u64 = 5;
+
+const NODE: u8 = 0;
+const TAG: u8 = 1;
+
+// byte and offset
+const BRANCH_LEN: usize = 9;
+
+pub struct VMapMmap<K, V> {
+    live: VMapTree<K, V>,
+    file: File,
+    map: Mmap,
+    // offsets of the nodes in the file, by hash
+    nodes: HashMap<Hash, u64>,
+    // offsets of the snapshots' roots
+    tags: HashMap<String, u64>,
+}
+
+/// A node read from the file, borrowing from it.
+struct NodeRef<'a> {
+    offset: u64,
+    hash: &'a [u8],
+    terminal: Option<&'a [u8]>,
+    branches: &'a [u8],
+}
+
+enum Record<'a> {
+    Node(NodeRef<'a>),
+    Tag(String, u64),
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or(io::ErrorKind::UnexpectedEof)?;
+        let taken = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> io::Result<usize> {
+        usize::try_from(self.u64()?).map_err(|_| invalid("length out of range"))
+    }
+}
+
+fn read_record(bytes: &[u8], offset: u64) -> io::Result<(Record<'_>, u64)> {
+    let pos = usize::try_from(offset).map_err(|_| invalid("offset out of range"))?;
+    let mut cursor = Cursor { bytes, pos };
+    let record = match cursor.byte()? {
+        NODE => {
+            let hash = cursor.take(32)?;
+            let terminal = match cursor.byte()? {
+                0 => None,
+                1 => {
+                    let len = cursor.len()?;
+                    Some(cursor.take(len)?)
+                }
+                _ => return Err(invalid("malformed node")),
+            };
+            let count = u16::from_le_bytes(cursor.take(2)?.try_into().unwrap());
+            let branches = cursor.take(count as usize * BRANCH_LEN)?;
+            Record::Node(NodeRef {
+                offset,
+                hash,
+                terminal,
+                branches,
+            })
+        }
+        TAG => {
+            let len = cursor.len()?;
+            let tag = String::from_utf8(cursor.take(len)?.to_vec())
+                .map_err(|_| invalid("tag isn't valid utf-8"))?;
+            Record::Tag(tag, cursor.u64()?)
+        }
+        _ => return Err(invalid("unknown record")),
+    };
+    Ok((record, cursor.pos as u64))
+}
+
+fn read_node(bytes: &[u8], offset: u64) -> io::Result<NodeRef<'_>> {
+    match read_record(bytes, offset)? {
+        (Record::Node(node), _) => Ok(node),
+        _ => Err(invalid("not a node")),
+    }
+}
+
+impl<'a> NodeRef<'a> {
+    fn branch(&self, i: usize) -> (u8, u64) {
+        let branch = &self.branches[i * BRANCH_LEN..(i + 1) * BRANCH_LEN];
+        (
+            branch[0],
+            u64::from_le_bytes(branch[1..].try_into().unwrap()),
+        )
+    }
+
+    fn child(&self, bytes: &'a [u8], b: u8) -> io::Result<Option<NodeRef<'a>>> {
+        let count = self.branches.len() / BRANCH_LEN;
+        let mut range = 0..count;
+        while !range.is_empty() {
+            let mid = (range.start + range.end) / 2;
+            let (byte, offset) = self.branch(mid);
+            if byte == b {
+                return self.child_at(bytes, offset).map(Some);
+            } else if byte < b {
+                range.start = mid + 1;
+            } else {
+                range.end = mid;
+            }
+        }
+        Ok(None)
+    }
+
+    fn child_at(&self, bytes: &'a [u8], offset: u64) -> io::Result<NodeRef<'a>> {
+        // children are written first, which also rules out cycles
+        if offset >= self.offset {
+            return Err(invalid("child written after its parent"));
+        }
+        read_node(bytes, offset)
+    }
+
+    fn for_each<F>(&self, bytes: &'a [u8], path: &mut Vec<u8>, f: &mut F) -> io::Result<()>
+    where
+        F: FnMut(&[u8], &'a [u8]) -> io::Result<()>,
+    {
+        if let Some(v) = self.terminal {
+            f(path, v)?;
+        }
+        for i in 0..self.branches.len() / BRANCH_LEN {
+            let (b, offset) = self.branch(i);
+            path.push(b);
+            self.child_at(bytes, offset)?.for_each(bytes, path, f)?;
+            path.pop();
+        }
+        Ok(())
+    }
+}
+
+impl<K, V> VMapMmap<K, V> {
+    /// Opens the file at `path`, creating it if missing, with its snapshots
+    /// and an empty live state. A record left incomplete by a crash while
+    /// checkpointing is dropped.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&[FORMAT_VERSION])?;
+            file.sync_data()?;
+        }
+        let map = map(&file)?;
+        if map.len() < HEADER_LEN as usize || &map[..4] != MAGIC {
+            return Err(invalid("not a snapshot file"));
+        }
+        if map[4] != FORMAT_VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+
+        let mut nodes = HashMap::new();
+        let mut tags = HashMap::new();
+        let mut offset = HEADER_LEN;
+        while offset < map.len() as u64 {
+            let (record, next) = match read_record(&map, offset) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            };
+            match record {
+                Record::Node(node) => {
+                    nodes.insert(node.hash.try_into().unwrap(), offset);
+                }
+                Record::Tag(tag, root) if root < offset => {
+                    tags.insert(tag, root);
+                }
+                Record::Tag(..) => return Err(invalid("tag written before its root")),
+            }
+            offset = next;
+        }
+        let map = if offset < map.len() as u64 {
+            drop(map);
+            file.set_len(offset)?;
+            self::map(&file)?
+        } else {
+            map
+        };
+
+        Ok(VMapMmap {
+            live: VMapTree::new(),
+            file,
+            map,
+            nodes,
+            tags,
+        })
+    }
+
+    pub fn live(&self) -> &VMapTree<K, V> {
+        &self.live
+    }
+
+    /// The live state, its own snapshots being kept in memory.
+    pub fn live_mut(&mut self) -> &mut VMapTree<K, V> {
+        &mut self.live
+    }
+
+    /// Sorted tags of the snapshots in the file.
+    pub fn tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self.tags.keys().map(String::as_str).collect();
+        tags.sort_unstable();
+        tags
+    }
+
+    /// Size of the file, in bytes.
+    pub fn file_len(&self) -> u64 {
+        self.map.len() as u64
+    }
+
+    /// Merkle hash of the snapshot `tag`, read from the file.
+    pub fn snapshot_hash(&self, tag: &str) -> io::Result<Option<Hash>> {
+        match self.tags.get(tag) {
+            Some(root) => Ok(Some(read_node(&self.map, *root)?.hash.try_into().unwrap())),
+            None => Ok(None),
+        }
+    }
+
+    /// Drops all snapshots, emptying the file.
+    pub fn prune(&mut self) -> io::Result<()> {
+        // not keeping bytes mapped past the end of the file
+        self.map = map_header(&self.file)?;
+        self.nodes.clear();
+        self.tags.clear();
+        self.file.set_len(HEADER_LEN)?;
+        self.file.sync_data()
+    }
+
+    fn root(&self, tag: &str) -> io::Result<NodeRef<'_>> {
+        let root = self.tags.get(tag).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no snapshot tagged {:?}", tag),
+            )
+        })?;
+        read_node(&self.map, *root)
+    }
+}
+
+impl<K, V> VMapMmap<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes,
+{
+    /// Saves the live state as `tag` in the file, replacing any snapshot
+    /// tagged the same, and writing only the nodes the file doesn't have.
+    pub fn checkpoint(&mut self, tag: String) -> io::Result<()> {
+        let mut records = vec![];
+        let file_len = self.map.len() as u64;
+        let root = append_node(&mut self.nodes, file_len, &self.live.root, &mut records);
+        records.push(TAG);
+        records.extend_from_slice(&(tag.len() as u64).to_le_bytes());
+        records.extend_from_slice(tag.as_bytes());
+        records.extend_from_slice(&root.to_le_bytes());
+
+        let written = (&self.file)
+            .write_all(&records)
+            .and_then(|_| self.file.sync_data())
+            .and_then(|_| map(&self.file));
+        match written {
+            Ok(map) => self.map = map,
+            Err(e) => {
+                // not leaving nodes behind which the index doesn't know of,
+                // nor indexing nodes past the end of the map
+                self.nodes
+                    .retain(|_, offset| *offset < self.map.len() as u64);
+                self.file.set_len(self.map.len() as u64)?;
+                return Err(e);
+            }
+        }
+        self.tags.insert(tag, root);
+        Ok(())
+    }
+
+    /// The bytes of the value of `k` in the snapshot `tag`, borrowed from
+    /// the file. An unknown tag is reported as [`io::ErrorKind::NotFound`].
+    pub fn get_bytes_at(&self, tag: &str, k: &K) -> io::Result<Option<&[u8]>> {
+        let mut node = self.root(tag)?;
+        for b in bytes(k) {
+            node = match node.child(&self.map, *b)? {
+                Some(child) => child,
+                None => return Ok(None),
+            };
+        }
+        Ok(node.terminal)
+    }
+
+    /// Same as [`VMapMmap::get_bytes_at`], reading the value.
+    pub fn get_at(&self, tag: &str, k: &K) -> io::Result<Option<V>> {
+        match self.get_bytes_at(tag, k)? {
+            Some(bytes) => V::read_from(bytes)
+                .map(Some)
+                .ok_or_else(|| invalid("unreadable value")),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the snapshot `tag` into the live state, returns whether
+    /// there's one.
+    pub fn rollback(&mut self, tag: &str) -> io::Result<bool> {
+        if !self.tags.contains_key(tag) {
+            return Ok(false);
+        }
+        let mut root = Node::new();
+        self.root(tag)?
+            .for_each(&self.map, &mut vec![], &mut |path, v| {
+                let v = V::read_from(v).ok_or_else(|| invalid("unreadable value"))?;
+                root.insert_shared(path.iter(), Rc::new(v));
+                Ok(())
+            })?;
+        self.live.root = root;
+        Ok(true)
+    }
+}
+
+// appends to `records` the nodes of the trie rooted at `node` which
+// aren't in the file yet, returns the offset of `node`
+fn append_node<V: AsFromBytes>(
+    nodes: &mut HashMap<Hash, u64>,
+    file_len: u64,
+    node: &Node<V>,
+    records: &mut Vec<u8>,
+) -> u64 {
+    let hash = node.hash();
+    if let Some(offset) = nodes.get(&hash) {
+        return *offset;
+    }
+    let branches: Vec<(u8, u64)> = node
+        .branches()
+        .map(|(b, child)| (b, append_node(nodes, file_len, child, records)))
+        .collect();
+    let offset = file_len + records.len() as u64;
+    records.push(NODE);
+    records.extend_from_slice(&hash);
+    match node.terminal() {
+        Some(v) => {
+            records.push(1);
+            records.extend_from_slice(&(v.as_bytes().len() as u64).to_le_bytes());
+            records.extend_from_slice(v.as_bytes());
+        }
+        None => records.push(0),
+    }
+    records.extend_from_slice(&(branches.len() as u16).to_le_bytes());
+    for (b, child) in branches {
+        records.push(b);
+        records.extend_from_slice(&child.to_le_bytes());
+    }
+    nodes.insert(hash, offset);
+    offset
+}
+
+fn map(file: &File) -> io::Result<Mmap> {
+    // SAFETY: the file is only ever appended to, or truncated to no
+    // shorter than the current map, which `prune` first shrinks to the
+    // header, so the bytes mapped don't change under the map, as long as
+    // nothing else writes to the file
+    unsafe { Mmap::map(file) }
+}
+
+/// Maps the header alone, which is all `prune` leaves.
+fn map_header(file: &File) -> io::Result<Mmap> {
+    // SAFETY: as for `map`, the file is never truncated below the header
+    unsafe { MmapOptions::new().len(HEADER_LEN as usize).map(file) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    use super::{VMapMmap, HEADER_LEN};
+    use crate::VersionedMap;
+
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let file = format!("tt-mmap-{}-{}", name, std::process::id());
+            TempFile(std::env::temp_dir().join(file))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    type Map = VMapMmap<String, String>;
+
+    fn key(k: &str) -> String {
+        k.to_owned()
+    }
+
+    #[test]
+    fn snapshots_are_read_from_the_file() {
+        let file = TempFile::new("read");
+        let mut map = Map::open(&file.0).unwrap();
+        for k in ["a", "ab", "b"] {
+            map.live_mut().insert(key(k), k.to_uppercase());
+        }
+        map.checkpoint("ONE".to_owned()).unwrap();
+        map.live_mut().insert(key("ab"), "changed".to_owned());
+        map.live_mut().remove(&key("b"));
+        map.checkpoint("TWO".to_owned()).unwrap();
+
+        let hash = map.live().root_hash();
+        let map = Map::open(&file.0).unwrap();
+        assert_eq!(vec!["ONE", "TWO"], map.tags());
+        assert_eq!(
+            Some(&b"AB"[..]),
+            map.get_bytes_at("ONE", &key("ab")).unwrap()
+        );
+        assert_eq!(
+            Some("changed".to_owned()),
+            map.get_at("TWO", &key("ab")).unwrap()
+        );
+        assert_eq!(None, map.get_at("TWO", &key("b")).unwrap());
+        assert_eq!(None, map.get_at("TWO", &key("abc")).unwrap());
+        assert!(map.get_at("THREE", &key("a")).is_err());
+        assert_eq!(Some(hash), map.snapshot_hash("TWO").unwrap());
+        assert!(map.live().entries().is_empty());
+    }
+
+    #[test]
+    fn checkpoints_append_new_nodes_only() {
+        let file = TempFile::new("append");
+        let mut map = Map::open(&file.0).unwrap();
+        for i in 0..100 {
+            map.live_mut().insert(format!("key/{}", i), i.to_string());
+        }
+        map.checkpoint("ONE".to_owned()).unwrap();
+        let len = map.file_len();
+
+        map.checkpoint("SAME".to_owned()).unwrap();
+        let tag_len = map.file_len() - len;
+        assert!(tag_len < 30);
+
+        map.live_mut().insert(key("key/5"), "changed".to_owned());
+        map.checkpoint("TWO".to_owned()).unwrap();
+        // the path of "key/5" and a tag
+        assert!(map.file_len() - len < tag_len + 6 * 100);
+
+        assert!(map.rollback("ONE").unwrap());
+        assert_eq!(Some(&"5".to_owned()), map.live().get(&key("key/5")));
+        assert_eq!(100, map.live().entries().len());
+        assert!(!map.rollback("THREE").unwrap());
+
+        map.prune().unwrap();
+        assert!(map.tags().is_empty());
+        assert_eq!(HEADER_LEN, map.file_len());
+        assert_eq!(None, map.snapshot_hash("ONE").unwrap());
+        map.checkpoint("ONE".to_owned()).unwrap();
+        assert_eq!(len, map.file_len());
+    }
+
+    #[test]
+    fn incomplete_records_are_dropped() {
+        let file = TempFile::new("crash");
+        let mut map = Map::open(&file.0).unwrap();
+        map.live_mut().insert(key("a"), "1".to_owned());
+        map.checkpoint("ONE".to_owned()).unwrap();
+        let len = map.file_len();
+        drop(map);
+
+        let mut out = OpenOptions::new().append(true).open(&file.0).unwrap();
+        out.write_all(&[0, 1, 2]).unwrap();
+        let map = Map::open(&file.0).unwrap();
+        assert_eq!(len, map.file_len());
+        assert_eq!(vec!["ONE"], map.tags());
+
+        fs::write(&file.0, b"nope").unwrap();
+        assert!(Map::open(&file.0).is_err());
+    }
+}
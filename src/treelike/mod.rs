@@ -1,30 +1,279 @@
-use std::{collections::HashMap, marker::PhantomData};
+use std::{collections::HashMap, fmt, marker::PhantomData, mem, ops::Index, rc::Rc};
 
 mod as_bytes;
+mod merkle;
+#[cfg(feature = "mmap")]
+pub mod mmap;
 mod node;
+pub mod sync;
+
+pub use as_bytes::AsFromBytes;
+pub use merkle::Proof;
+use node::{Builder, Census, Node};
+// `None` when nothing is stored under the path leading to it
+type Subtree<V> = Option<Rc<Node<V>>>;
 
-use as_bytes::AsFromBytes;
-use node::Node;
 #[allow(unused)]
 type VersionedMapTreeLike<K, V> = VMapTree<K, V>;
 
 pub struct VMapTree<K, V> {
     root: Node<V>,
     snapshots: HashMap<String, Node<V>>,
+    // keyed by prefix and tag
+    prefix_snapshots: HashMap<(Vec<u8>, String), Subtree<V>>,
 
     _phantom_data: PhantomData<K>,
 }
 impl<K, V> VMapTree<K, V> {
-    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         Self {
             root: Node::new(),
             snapshots: HashMap::new(),
+            prefix_snapshots: HashMap::new(),
             _phantom_data: PhantomData,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub keys: usize,
+    /// snapshots of the whole map
+    pub snapshots: usize,
+    /// snapshots of the keys under a prefix
+    pub prefix_snapshots: usize,
+    /// nodes of the live trie and of all snapshots, as if nothing was shared
+    pub nodes: usize,
+    /// nodes actually allocated, counting each shared node once
+    pub unique_nodes: usize,
+    /// approximate, not counting memory owned by keys and values
+    pub heap_bytes: usize,
+}
+
+impl<K, V> VMapTree<K, V> {
+    pub fn stats(&self) -> Stats {
+        let mut census = Census::new();
+        let mut nodes = self.root.census(&mut census);
+        for node in self.snapshots.values() {
+            nodes += node.census(&mut census);
+        }
+        for rc in self.prefix_snapshots.values().flatten() {
+            nodes += Node::census_shared(rc, &mut census);
+        }
+        let tags_bytes: usize = self.snapshots.keys().map(String::capacity).sum::<usize>()
+            + self
+                .prefix_snapshots
+                .keys()
+                .map(|(prefix, tag)| prefix.capacity() + tag.capacity())
+                .sum::<usize>();
+        Stats {
+            keys: self.root.len(),
+            snapshots: self.snapshots.len(),
+            prefix_snapshots: self.prefix_snapshots.len(),
+            nodes,
+            // roots aren't shared, one for the live trie and one per snapshot
+            unique_nodes: census.shared_nodes() + 1 + self.snapshots.len(),
+            heap_bytes: census.heap_bytes()
+                + crate::hash_map_bytes(&self.snapshots)
+                + crate::hash_map_bytes(&self.prefix_snapshots)
+                + tags_bytes,
+        }
+    }
+}
+
+impl<K, V> VMapTree<K, V>
+where
+    K: AsFromBytes,
+{
+    /// Same as [`insert`](crate::VersionedMap::insert), but hands the
+    /// previous value back as it's stored, so it never has to be cloned
+    /// when a snapshot still holds it.
+    pub fn insert_shared(&mut self, k: K, v: V) -> Option<Rc<V>> {
+        let iter = bytes(&k).iter();
+        self.root.insert_shared(iter, Rc::new(v))
+    }
+
+    /// Same as [`remove`](crate::VersionedMap::remove), returning the value
+    /// as it's stored.
+    pub fn remove_shared(&mut self, k: &K) -> Option<Rc<V>> {
+        let iter = bytes(k).iter();
+        let (_, res) = self.root.remove_shared(iter);
+        res
+    }
+
+    /// Builds a map out of `sorted`, in ascending order of key bytes,
+    /// bottom-up. Of equal keys the last one is kept. Panics if the keys
+    /// aren't sorted.
+    pub fn bulk_load<I: IntoIterator<Item = (K, V)>>(sorted: I) -> Self {
+        let mut builder = Builder::new();
+        for (k, v) in sorted {
+            builder.push(bytes(&k), v);
+        }
+        let mut map = VMapTree::new();
+        map.root = builder.finish();
+        map
+    }
+
+    /// Same as inserting `entries` one by one, but walks the path shared
+    /// by several keys once, copying the nodes snapshots hold at most once.
+    /// Returns how many keys weren't there before.
+    pub fn insert_batch<I: IntoIterator<Item = (K, V)>>(&mut self, entries: I) -> usize {
+        let mut entries: Vec<(K, Option<Rc<V>>)> = entries
+            .into_iter()
+            .map(|(k, v)| (k, Some(Rc::new(v))))
+            .collect();
+        // the last of equal keys wins, as it would inserted one by one
+        entries.reverse();
+        entries.sort_by(|(a, _), (b, _)| bytes(a).cmp(bytes(b)));
+        entries.dedup_by(|(a, _), (b, _)| a.as_bytes() == b.as_bytes());
+        let mut sorted: Vec<(&[u8], Option<Rc<V>>)> = entries
+            .iter_mut()
+            .map(|(k, v)| (bytes(k), v.take()))
+            .collect();
+        self.root.insert_sorted(&mut sorted, 0)
+    }
+
+    /// Same as [`VMapTree::insert_batch`] for removing `keys`. Returns how
+    /// many of them were there.
+    pub fn remove_batch<'a, I>(&mut self, keys: I) -> usize
+    where
+        I: IntoIterator<Item = &'a K>,
+        K: 'a,
+    {
+        let mut keys: Vec<&[u8]> = keys.into_iter().map(bytes).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        let (_, removed) = self.root.remove_sorted(&keys, 0);
+        removed
+    }
+
+    /// Saves the values of the keys starting with `prefix` under `tag`,
+    /// for [`VMapTree::rollback_prefix`] to restore. Tags of different
+    /// prefixes don't clash with each other, nor with the tags of
+    /// [`checkpoint`](crate::VersionedMap::checkpoint).
+    pub fn checkpoint_prefix(&mut self, prefix: &K, tag: String) {
+        let prefix = bytes(prefix);
+        let subtree = self.root.subtree(prefix.iter());
+        self.prefix_snapshots.insert((prefix.to_vec(), tag), subtree);
+    }
+
+    /// Restores the keys starting with `prefix` to how they were at
+    /// `checkpoint_prefix(prefix, tag)`, leaving all other keys as they are.
+    pub fn rollback_prefix(&mut self, prefix: &K, tag: String) -> bool {
+        let prefix = bytes(prefix);
+        match self.prefix_snapshots.get(&(prefix.to_vec(), tag)) {
+            Some(subtree) => {
+                self.root.attach(prefix.iter(), subtree.clone());
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<K, V> VMapTree<K, V>
+where
+    K: AsFromBytes,
+    V: AsFromBytes,
+{
+    /// Merkle hash of the live state, equal for maps holding the same keys
+    /// and values. Only the nodes changed since it was last asked for are
+    /// hashed again.
+    pub fn root_hash(&self) -> crate::Hash {
+        self.root.hash()
+    }
+
+    /// Same as [`VMapTree::root_hash`] for the snapshot `tag`.
+    pub fn snapshot_hash(&self, tag: &str) -> Option<crate::Hash> {
+        self.snapshots.get(tag).map(Node::hash)
+    }
+
+    /// Proof of the live value of `k`, or of its absence, which
+    /// [`Proof::verify`] checks against [`VMapTree::root_hash`].
+    pub fn prove(&self, k: &K) -> Proof {
+        Proof {
+            levels: self.root.prove(bytes(k)),
+        }
+    }
+
+    /// Same as [`VMapTree::prove`] in the snapshot `tag`.
+    pub fn prove_snapshot(&self, tag: &str, k: &K) -> Option<Proof> {
+        let levels = self.snapshots.get(tag)?.prove(bytes(k));
+        Some(Proof { levels })
+    }
+}
+
+impl<K, V> VMapTree<K, V>
+where
+    K: AsFromBytes,
+    V: Clone,
+{
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V> {
+        let (node, depth) = self.root.descend_mut(bytes(&k).iter());
+        Entry {
+            key: k,
+            node,
+            depth,
+        }
+    }
+
+    /// Applies `f` to the value of `k` in place, returns whether `k` was present.
+    pub fn update<F: FnOnce(&mut V)>(&mut self, k: &K, f: F) -> bool {
+        let key = bytes(k);
+        let (node, depth) = self.root.descend_mut(key.iter());
+        if depth < key.len() {
+            return false;
+        }
+        match node.terminal_mut() {
+            Some(v) => {
+                f(v);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// A view into a single key of a [`VMapTree`], obtained with
+/// [`VMapTree::entry`]. The path to the key is copied away from the
+/// snapshots sharing it once, when the entry is created.
+pub struct Entry<'a, K, V> {
+    key: K,
+    // deepest existing node on the path to `key`
+    node: &'a mut Node<V>,
+    // number of bytes of `key` leading to `node`
+    depth: usize,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: AsFromBytes,
+    V: Clone,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        let Entry { key, node, depth } = self;
+        node.grow(bytes(&key)[depth..].iter())
+            .terminal_or_insert_with(f)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        if self.depth == bytes(&self.key).len() {
+            if let Some(v) = self.node.terminal_mut() {
+                f(v);
+            }
+        }
+        self
+    }
+}
+
 fn bytes<K: AsFromBytes>(input: &K) -> &[u8] {
     let bytes = input.as_bytes();
     if bytes.is_empty() {
@@ -64,13 +313,467 @@ where
         false
     }
 
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String> {
+        let snapshot = self.snapshots.get(&tag)?.clone();
+        let saved = save_as
+            .unwrap_or_else(|| crate::autosave_tag(|tag| self.snapshots.contains_key(tag)));
+        let current = mem::replace(&mut self.root, snapshot);
+        self.snapshots.insert(saved.clone(), current);
+        Some(saved)
+    }
+
     fn prune(&mut self) {
         for (_snapshot_tag, _node) in self.snapshots.drain() {}
+        self.prefix_snapshots.clear();
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self.snapshots.keys().map(String::as_str).collect();
+        tags.sort_unstable();
+        tags
+    }
+
+    fn entries(&self) -> Vec<(K, &V)> {
+        let mut entries = vec![];
+        self.root
+            .for_each(&mut vec![], &mut |path, v| entries.push((read_key(path), v)));
+        entries
     }
 }
 
+impl<K, V> Extend<(K, V)> for VMapTree<K, V>
+where
+    K: AsFromBytes,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.insert_batch(iter);
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for VMapTree<K, V>
+where
+    K: AsFromBytes,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = VMapTree::new();
+        map.insert_batch(iter);
+        map
+    }
+}
+
+impl<K, V> Default for VMapTree<K, V> {
+    fn default() -> Self {
+        VMapTree::new()
+    }
+}
+
+// not derived, as the copy shares the tries with the original, which
+// doesn't need `K: Clone` or `V: Clone`, and costs the same whatever
+// the number of keys
+impl<K, V> Clone for VMapTree<K, V> {
+    fn clone(&self) -> Self {
+        VMapTree {
+            root: self.root.clone(),
+            snapshots: self.snapshots.clone(),
+            prefix_snapshots: self.prefix_snapshots.clone(),
+            _phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<K, V> fmt::Debug for VMapTree<K, V>
+where
+    K: AsFromBytes + fmt::Debug,
+    V: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        crate::fmt_map(self, "VMapTree", f)
+    }
+}
+
+/// Compares the live entries only, whatever the snapshots. Subtrees the
+/// maps share aren't walked.
+impl<K, V> PartialEq for VMapTree<K, V>
+where
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let mut equal = true;
+        let mut compare = |_: &[u8], a: Option<&V>, b: Option<&V>| equal &= a == b;
+        Node::diff(Some(&self.root), Some(&other.root), &mut vec![], &mut compare);
+        equal
+    }
+}
+
+impl<K, V> Eq for VMapTree<K, V> where V: Eq {}
+
+impl<K, V> Index<&K> for VMapTree<K, V>
+where
+    K: AsFromBytes,
+{
+    type Output = V;
+
+    /// Panics if `k` isn't in the live map.
+    fn index(&self, k: &K) -> &V {
+        self.root.get(bytes(k).iter()).expect("no entry found for key")
+    }
+}
+
+/// Live entries, in byte order of the keys.
+impl<K, V> IntoIterator for VMapTree<K, V>
+where
+    K: AsFromBytes,
+    V: Clone,
+{
+    type Item = (K, V);
+    type IntoIter = std::vec::IntoIter<(K, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        // dropped first, so as to not share the values anymore
+        drop(self.snapshots);
+        drop(self.prefix_snapshots);
+        let values = self.root.into_values().into_iter();
+        let entries: Vec<(K, V)> = values.map(|(path, v)| (read_key(&path), v)).collect();
+        entries.into_iter()
+    }
+}
+
+impl<K, V> crate::Diff<K, V> for VMapTree<K, V>
+where
+    K: AsFromBytes,
+    V: Clone + PartialEq,
+{
+    fn diff_live(&self, tag: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let snapshot = self.snapshots.get(tag)?;
+        Some(diff_nodes(&self.root, snapshot))
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let from = self.snapshots.get(from)?;
+        let to = self.snapshots.get(to)?;
+        Some(diff_nodes(from, to))
+    }
+}
+
+fn diff_nodes<K, V>(old: &Node<V>, new: &Node<V>) -> Vec<crate::Change<K, V>>
+where
+    K: AsFromBytes,
+    V: Clone + PartialEq,
+{
+    let mut changes = vec![];
+    Node::diff(Some(old), Some(new), &mut vec![], &mut |path, old, new| {
+        changes.extend(crate::change(read_key(path), old, new));
+    });
+    changes
+}
+
+fn read_key<K: AsFromBytes>(path: &[u8]) -> K {
+    K::read_from(path).expect("stored keys are read back")
+}
+
 #[cfg(test)]
 use crate::test_helpers::common_tests::versioned_map_trait_tests;
 
 #[cfg(test)]
 versioned_map_trait_tests!(VMapTree);
+
+#[cfg(test)]
+mod stats_tests {
+    use super::VMapTree;
+    use crate::VersionedMap;
+
+    #[test]
+    fn stats_count_shared_nodes_once() {
+        let mut map = VMapTree::<String, u32>::new();
+        map.insert("a".to_owned(), 1);
+        map.insert("ab".to_owned(), 2);
+
+        let stats = map.stats();
+        assert_eq!(2, stats.keys);
+        assert_eq!(0, stats.snapshots);
+        assert_eq!(3, stats.nodes);
+        assert_eq!(3, stats.unique_nodes);
+
+        map.checkpoint("ONE".to_owned());
+        let stats = map.stats();
+        assert_eq!(1, stats.snapshots);
+        assert_eq!(6, stats.nodes);
+        assert_eq!(4, stats.unique_nodes);
+
+        map.insert("ab".to_owned(), 3);
+        let stats = map.stats();
+        assert_eq!(2, stats.keys);
+        assert_eq!(6, stats.nodes);
+        assert_eq!(6, stats.unique_nodes);
+        assert!(stats.heap_bytes > 0);
+
+        map.checkpoint_prefix(&"a".to_owned(), "ONE".to_owned());
+        let stats = map.stats();
+        assert_eq!(1, stats.snapshots);
+        assert_eq!(1, stats.prefix_snapshots);
+
+        map.prune();
+        assert_eq!(3, map.stats().unique_nodes);
+        assert_eq!(0, map.stats().prefix_snapshots);
+    }
+}
+
+#[cfg(test)]
+mod batch_tests {
+    use quickcheck::{QuickCheck, TestResult};
+
+    use super::VMapTree;
+    use crate::VersionedMap;
+
+    fn contents(map: &VMapTree<String, u32>) -> Vec<(String, u32)> {
+        map.entries().into_iter().map(|(k, v)| (k, *v)).collect()
+    }
+
+    #[test]
+    fn batches_match_one_by_one() {
+        fn property(inserts: Vec<(String, u32)>, removals: Vec<String>) -> TestResult {
+            let inserts: Vec<(String, u32)> =
+                inserts.into_iter().filter(|(k, _)| !k.is_empty()).collect();
+            let removals: Vec<String> = removals.into_iter().filter(|k| !k.is_empty()).collect();
+
+            let mut one_by_one = VMapTree::new();
+            one_by_one.insert("a".to_owned(), 0);
+            one_by_one.checkpoint("BEFORE".to_owned());
+            let mut batched = VMapTree::new();
+            batched.insert("a".to_owned(), 0);
+            batched.checkpoint("BEFORE".to_owned());
+
+            let mut added = 0;
+            for (k, v) in inserts.clone() {
+                added += one_by_one.insert(k, v).is_none() as usize;
+            }
+            assert_eq!(added, batched.insert_batch(inserts));
+            assert_eq!(contents(&one_by_one), contents(&batched));
+
+            let mut removed = 0;
+            for k in removals.iter().chain(&removals) {
+                removed += one_by_one.remove(k).is_some() as usize;
+            }
+            assert_eq!(removed, batched.remove_batch(removals.iter().chain(&removals)));
+            assert_eq!(contents(&one_by_one), contents(&batched));
+            assert_eq!(one_by_one.stats(), batched.stats());
+
+            assert!(batched.rollback("BEFORE".to_owned()));
+            assert_eq!(vec![("a".to_owned(), 0)], contents(&batched));
+            TestResult::passed()
+        }
+        QuickCheck::new().quickcheck(property as fn(Vec<(String, u32)>, Vec<String>) -> TestResult);
+    }
+
+    #[test]
+    fn bulk_load_builds_the_same_trie() {
+        let keys = ["a", "ab", "abc", "abd", "b", "b", "ba", "c"];
+        let sorted = keys.iter().enumerate().map(|(i, k)| (k.to_string(), i as u32));
+        let loaded = VMapTree::bulk_load(sorted.clone());
+        let mut inserted = VMapTree::new();
+        for (k, v) in sorted {
+            inserted.insert(k, v);
+        }
+        assert_eq!(contents(&inserted), contents(&loaded));
+        assert_eq!(Some(&5), loaded.get(&"b".to_owned()));
+        assert_eq!(inserted.stats(), loaded.stats());
+
+        let collected: VMapTree<String, u32> = contents(&inserted).into_iter().rev().collect();
+        assert_eq!(inserted.stats(), collected.stats());
+    }
+
+    #[test]
+    #[should_panic(expected = "ascending")]
+    fn bulk_load_needs_sorted_keys() {
+        VMapTree::bulk_load(vec![("b".to_owned(), 1), ("a".to_owned(), 2)]);
+    }
+}
+
+#[cfg(test)]
+mod prefix_tests {
+    use super::VMapTree;
+    use crate::VersionedMap;
+
+    fn filled() -> VMapTree<String, u32> {
+        let mut map = VMapTree::new();
+        for (i, key) in ["t1", "t1/a", "t1/b", "t2/a", "t2/b"].iter().enumerate() {
+            map.insert(key.to_string(), i as u32);
+        }
+        map
+    }
+
+    #[test]
+    fn rollback_prefix_leaves_other_keys_alone() {
+        let mut map = filled();
+        let tenant = "t1/".to_owned();
+
+        map.checkpoint_prefix(&tenant, "A".to_owned());
+        for key in ["t1", "t1/a", "t2/a"] {
+            map.insert(key.to_owned(), 10);
+        }
+        map.remove(&"t1/b".to_owned());
+        map.insert("t1/c".to_owned(), 10);
+
+        assert!(map.rollback_prefix(&tenant, "A".to_owned()));
+        assert_eq!(Some(&1), map.get(&"t1/a".to_owned()));
+        assert_eq!(Some(&2), map.get(&"t1/b".to_owned()));
+        assert_eq!(None, map.get(&"t1/c".to_owned()));
+        assert_eq!(Some(&10), map.get(&"t1".to_owned()));
+        assert_eq!(Some(&10), map.get(&"t2/a".to_owned()));
+        assert_eq!(Some(&4), map.get(&"t2/b".to_owned()));
+    }
+
+    #[test]
+    fn rollback_prefix_to_empty_namespace() {
+        let mut map = filled();
+        let tenant = "t3/".to_owned();
+
+        map.checkpoint_prefix(&tenant, "EMPTY".to_owned());
+        map.insert("t3/a".to_owned(), 10);
+        map.insert("t3/b/c".to_owned(), 10);
+
+        assert!(map.rollback_prefix(&tenant, "EMPTY".to_owned()));
+        assert_eq!(None, map.get(&"t3/a".to_owned()));
+        assert_eq!(None, map.get(&"t3/b/c".to_owned()));
+        assert_eq!(5, map.stats().keys);
+        // nothing but the root and the paths of the 5 initial keys is left
+        assert_eq!(map.stats().nodes, filled().stats().nodes);
+    }
+
+    #[test]
+    fn prefix_tags_are_per_prefix() {
+        let mut map = filled();
+
+        map.checkpoint_prefix(&"t1/".to_owned(), "A".to_owned());
+        assert!(!map.rollback_prefix(&"t2/".to_owned(), "A".to_owned()));
+        assert!(!map.rollback("A".to_owned()));
+        assert!(!map.rollback_prefix(&"t1/".to_owned(), "B".to_owned()));
+
+        map.prune();
+        assert!(!map.rollback_prefix(&"t1/".to_owned(), "A".to_owned()));
+    }
+}
+
+#[cfg(test)]
+mod ownership_tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::VMapTree;
+    use crate::VersionedMap;
+
+    struct CountedClones(Rc<Cell<usize>>);
+
+    impl Clone for CountedClones {
+        fn clone(&self) -> Self {
+            self.0.set(self.0.get() + 1);
+            CountedClones(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn unshared_values_are_moved_out() {
+        let clones = Rc::new(Cell::new(0));
+        let mut map = VMapTree::<String, CountedClones>::new();
+        let key = "key".to_owned();
+
+        map.insert(key.clone(), CountedClones(clones.clone()));
+        assert!(map.insert(key.clone(), CountedClones(clones.clone())).is_some());
+        assert!(map.remove(&key).is_some());
+        assert_eq!(0, clones.get());
+    }
+
+    #[test]
+    fn values_shared_with_snapshots_are_cloned() {
+        let clones = Rc::new(Cell::new(0));
+        let mut map = VMapTree::<String, CountedClones>::new();
+        let key = "key".to_owned();
+
+        map.insert(key.clone(), CountedClones(clones.clone()));
+        map.checkpoint("ONE".to_owned());
+        assert!(map.insert(key.clone(), CountedClones(clones.clone())).is_some());
+        assert_eq!(1, clones.get());
+
+        map.checkpoint("TWO".to_owned());
+        let shared = map.insert_shared(key.clone(), CountedClones(clones.clone()));
+        assert_eq!(2, Rc::strong_count(&shared.unwrap()));
+        assert!(map.remove_shared(&key).is_some());
+        assert_eq!(1, clones.get());
+    }
+}
+
+#[cfg(test)]
+mod merkle_tests {
+    use super::VMapTree;
+    use crate::VersionedMap;
+
+    fn filled(keys: &[&str]) -> VMapTree<String, String> {
+        let mut map = VMapTree::new();
+        for key in keys {
+            map.insert(key.to_string(), key.to_uppercase());
+        }
+        map
+    }
+
+    #[test]
+    fn hashes_depend_on_contents_only() {
+        let mut map = filled(&["a", "ab", "b"]);
+        assert_eq!(filled(&["b", "ab", "a"]).root_hash(), map.root_hash());
+        assert_ne!(filled(&["a", "ab"]).root_hash(), map.root_hash());
+
+        map.checkpoint("ONE".to_owned());
+        map.insert("abc".to_owned(), "X".to_owned());
+        map.remove(&"abc".to_owned());
+        *map.entry("b".to_owned()).or_insert_with(String::new) = "Y".to_owned();
+        map.update(&"a".to_owned(), |v| v.push('!'));
+        let mut expected = filled(&["ab"]);
+        expected.insert("a".to_owned(), "A!".to_owned());
+        expected.insert("b".to_owned(), "Y".to_owned());
+        assert_eq!(expected.root_hash(), map.root_hash());
+        assert_eq!(
+            filled(&["a", "ab", "b"]).root_hash(),
+            map.snapshot_hash("ONE").unwrap()
+        );
+        assert_eq!(None, map.snapshot_hash("TWO"));
+
+        map.checkpoint_prefix(&"a".to_owned(), "A".to_owned());
+        map.insert("ab".to_owned(), "Z".to_owned());
+        map.rollback_prefix(&"a".to_owned(), "A".to_owned());
+        assert_eq!(expected.root_hash(), map.root_hash());
+
+        map.rollback("ONE".to_owned());
+        assert_eq!(map.snapshot_hash("ONE").unwrap(), map.root_hash());
+    }
+
+    #[test]
+    fn proofs_check_against_the_root_hash() {
+        let map = filled(&["a", "abc", "b"]);
+        let root = map.root_hash();
+        let key = |k: &str| k.to_owned();
+        let value = |v: &str| Some(v.to_owned());
+
+        let proof = map.prove(&key("abc"));
+        assert!(proof.verify(&root, &key("abc"), value("ABC").as_ref()));
+        assert!(!proof.verify(&root, &key("abc"), value("X").as_ref()));
+        assert!(!proof.verify(&root, &key("abc"), None::<&String>));
+        assert!(!proof.verify(&root, &key("abd"), value("ABC").as_ref()));
+        let other = filled(&["a"]).root_hash();
+        assert!(!proof.verify(&other, &key("abc"), value("ABC").as_ref()));
+
+        // missing at the end of an existing path, and off the paths
+        for missing in ["ab", "ac", "abcd", "c"] {
+            let proof = map.prove(&key(missing));
+            assert!(proof.verify(&root, &key(missing), None::<&String>));
+            assert!(!proof.verify(&root, &key(missing), value("X").as_ref()));
+        }
+        let proof = map.prove(&key("ab"));
+        assert!(!proof.verify(&root, &key("a"), None::<&String>));
+
+        let mut later = map;
+        later.checkpoint("ONE".to_owned());
+        later.remove(&key("abc"));
+        let proof = later.prove_snapshot("ONE", &key("abc")).unwrap();
+        assert!(proof.verify(&root, &key("abc"), value("ABC").as_ref()));
+        assert!(later.prove_snapshot("TWO", &key("abc")).is_none());
+    }
+}
@@ -1,13 +1,67 @@
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::rc::Rc;
 use std::slice::Iter;
 
+use super::as_bytes::AsFromBytes;
+use super::merkle::{self, Level};
+use crate::Hash;
+
 const BYTE_VALS: usize = 256;
 
-#[derive(Clone)]
 pub struct Node<V> {
     terminal: Option<Rc<V>>,
 
     branches: [Option<Rc<Node<V>>>; BYTE_VALS],
+
+    // computed on demand, and forgotten by anything that may change the
+    // node, so that only the paths copied on write are hashed again
+    hash: Cell<Option<Hash>>,
+}
+
+// not derived, as copying a node only bumps the reference counts
+// of its terminal and branches and doesn't need `V: Clone`
+impl<V> Clone for Node<V> {
+    fn clone(&self) -> Self {
+        Node {
+            terminal: self.terminal.clone(),
+            branches: self.branches.clone(),
+            hash: self.hash.clone(),
+        }
+    }
+}
+
+/// Moves the value out of `rc` if nothing else shares it, clones it otherwise.
+fn into_owned<V: Clone>(rc: Rc<V>) -> V {
+    Rc::try_unwrap(rc).unwrap_or_else(|rc| (*rc).clone())
+}
+
+/// Distinct nodes and values met while walking tries that share them.
+pub struct Census<V> {
+    sizes: HashMap<*const Node<V>, usize>,
+    values: HashSet<*const V>,
+}
+
+impl<V> Census<V> {
+    pub fn new() -> Self {
+        Census {
+            sizes: HashMap::new(),
+            values: HashSet::new(),
+        }
+    }
+
+    /// Shared nodes met, not counting the roots the walks started from.
+    pub fn shared_nodes(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// Approximate heap taken by the distinct shared nodes and values met.
+    pub fn heap_bytes(&self) -> usize {
+        let rc_header = 2 * mem::size_of::<usize>();
+        self.sizes.len() * (mem::size_of::<Node<V>>() + rc_header)
+            + self.values.len() * (mem::size_of::<V>() + rc_header)
+    }
 }
 
 impl<V> Node<V> {
@@ -16,39 +70,184 @@ impl<V> Node<V> {
         Node {
             terminal: None,
             branches: array,
+            hash: Cell::new(None),
         }
     }
-}
 
-impl<V: Clone> Node<V> {
-    pub fn insert(&mut self, mut iter: Iter<'_, u8>, v: V) -> Option<V> {
+    fn forget_hash(&mut self) {
+        *self.hash.get_mut() = None;
+    }
+
+    /// Nodes on the path are mutated in place when uniquely owned, and only
+    /// copied when a snapshot shares them.
+    pub fn insert_shared(&mut self, mut iter: Iter<'_, u8>, v: Rc<V>) -> Option<Rc<V>> {
+        self.forget_hash();
         match iter.next() {
+            None => self.terminal.replace(v),
+            Some(b) => {
+                let branch = self.branches[*b as usize]
+                    .get_or_insert_with(|| Rc::new(Node::new()));
+                Rc::make_mut(branch).insert_shared(iter, v)
+            }
+        }
+    }
+
+    /// Number of values stored under `self`.
+    pub fn len(&self) -> usize {
+        let below: usize = self.branches.iter().flatten().map(|rc| rc.len()).sum();
+        below + self.terminal.iter().count()
+    }
+
+    /// Number of nodes in the trie rooted at `self`, counting `self`.
+    /// Registers the nodes and values met in `census`, and doesn't descend
+    /// into the ones already registered by previous walks.
+    pub fn census(&self, census: &mut Census<V>) -> usize {
+        if let Some(rc) = &self.terminal {
+            census.values.insert(Rc::as_ptr(rc));
+        }
+        let below: usize = self
+            .branches
+            .iter()
+            .flatten()
+            .map(|rc| Node::census_shared(rc, census))
+            .sum();
+        1 + below
+    }
+
+    /// Same as [`Node::census`] for a node that may be shared, which is
+    /// registered in `census` itself.
+    pub fn census_shared(rc: &Rc<Node<V>>, census: &mut Census<V>) -> usize {
+        let ptr = Rc::as_ptr(rc);
+        match census.sizes.get(&ptr) {
+            Some(known) => *known,
             None => {
-                let result = self.terminal.take().map(|rc| (*rc).clone());
-                self.terminal = Some(Rc::new(v));
-                result
+                let size = rc.census(census);
+                census.sizes.insert(ptr, size);
+                size
             }
-            Some(b) => {
-                let prev = self.branches[*b as usize].take();
-
-                match prev {
-                    None => {
-                        let mut node = Node::new();
-                        let result = node.insert(iter, v);
-                        self.branches[*b as usize] = Some(Rc::new(node));
-                        result
-                    }
-                    Some(rc) => {
-                        let mut slight_copy = (*rc).clone();
-                        let result = slight_copy.insert(iter, v);
-                        self.branches[*b as usize] = Some(Rc::new(slight_copy));
-                        result
-                    }
+        }
+    }
+
+    /// The subtree reached by following `iter`, holding the values of the
+    /// keys starting with it.
+    pub fn subtree(&self, mut iter: Iter<'_, u8>) -> Option<Rc<Node<V>>> {
+        let b = iter.next().expect("path to a subtree isn't empty");
+        let branch = self.branches[*b as usize].as_ref()?;
+        if iter.len() == 0 {
+            Some(branch.clone())
+        } else {
+            branch.subtree(iter)
+        }
+    }
+
+    /// Puts `subtree` where following `iter` leads, in place of whatever
+    /// was there. Nodes left empty by detaching a subtree are removed,
+    /// the returned flag tells whether `self` is one of them.
+    pub fn attach(&mut self, mut iter: Iter<'_, u8>, subtree: Option<Rc<Node<V>>>) -> bool {
+        self.forget_hash();
+        let b = *iter.next().expect("path to a subtree isn't empty") as usize;
+        if iter.len() == 0 {
+            self.branches[b] = subtree;
+        } else if subtree.is_some() {
+            let branch = self.branches[b].get_or_insert_with(|| Rc::new(Node::new()));
+            Rc::make_mut(branch).attach(iter, subtree);
+        } else if let Some(branch) = self.branches[b].as_mut() {
+            if Rc::make_mut(branch).attach(iter, None) {
+                self.branches[b] = None;
+            }
+        }
+        self.terminal.is_none() && !self.branches.iter().any(|el| el.is_some())
+    }
+
+    /// Calls `f` with the path and both values of every key which isn't
+    /// stored the same in `this` and `other`, not descending into the
+    /// subtrees they share.
+    pub fn diff<F>(this: Option<&Node<V>>, other: Option<&Node<V>>, path: &mut Vec<u8>, f: &mut F)
+    where
+        F: FnMut(&[u8], Option<&V>, Option<&V>),
+    {
+        let terminals = (
+            this.and_then(|node| node.terminal.as_ref()),
+            other.and_then(|node| node.terminal.as_ref()),
+        );
+        match terminals {
+            (None, None) => {}
+            (Some(a), Some(b)) if Rc::ptr_eq(a, b) => {}
+            (a, b) => f(path, a.map(|rc| &**rc), b.map(|rc| &**rc)),
+        }
+        for b in 0..BYTE_VALS {
+            let branches = (
+                this.and_then(|node| node.branches[b].as_ref()),
+                other.and_then(|node| node.branches[b].as_ref()),
+            );
+            match branches {
+                (None, None) => {}
+                (Some(x), Some(y)) if Rc::ptr_eq(x, y) => {}
+                (x, y) => {
+                    path.push(b as u8);
+                    Node::diff(x.map(|rc| &**rc), y.map(|rc| &**rc), path, f);
+                    path.pop();
                 }
             }
         }
     }
 
+    /// Calls `f` with the path and value of every key stored under `self`,
+    /// in byte order.
+    pub fn for_each<'a, F>(&'a self, path: &mut Vec<u8>, f: &mut F)
+    where
+        F: FnMut(&[u8], &'a V),
+    {
+        if let Some(v) = &self.terminal {
+            f(path, v);
+        }
+        for (b, branch) in self.branches.iter().enumerate() {
+            if let Some(node) = branch {
+                path.push(b as u8);
+                node.for_each(path, f);
+                path.pop();
+            }
+        }
+    }
+
+    /// Same as [`Node::for_each`], handing over the values, which the
+    /// nodes no longer shared by anything else give up without a copy.
+    pub fn into_each<F>(self, path: &mut Vec<u8>, f: &mut F)
+    where
+        F: FnMut(&[u8], Rc<V>),
+    {
+        if let Some(v) = self.terminal {
+            f(path, v);
+        }
+        for (b, branch) in self.branches.into_iter().enumerate() {
+            if let Some(rc) = branch {
+                path.push(b as u8);
+                Rc::unwrap_or_clone(rc).into_each(path, f);
+                path.pop();
+            }
+        }
+    }
+
+    /// The node reached by following `iter`, `self` if it's empty.
+    pub fn descend(&self, iter: Iter<'_, u8>) -> Option<&Node<V>> {
+        let mut node = self;
+        for b in iter {
+            node = node.branches[*b as usize].as_ref()?;
+        }
+        Some(node)
+    }
+
+    pub fn terminal(&self) -> Option<&V> {
+        self.terminal.as_deref()
+    }
+
+    /// Branches by byte.
+    #[cfg(feature = "mmap")]
+    pub fn branches(&self) -> impl Iterator<Item = (u8, &Node<V>)> {
+        let branches = self.branches.iter().enumerate();
+        branches.filter_map(|(b, branch)| Some((b as u8, &**branch.as_ref()?)))
+    }
+
     pub fn get(&self, mut iter: Iter<'_, u8>) -> Option<&V> {
         match iter.next() {
             None => self.terminal.as_deref(),
@@ -59,10 +258,11 @@ impl<V: Clone> Node<V> {
         }
     }
 
-    pub fn remove(&mut self, mut iter: Iter<'_, u8>) -> (bool, Option<V>) {
+    pub fn remove_shared(&mut self, mut iter: Iter<'_, u8>) -> (bool, Option<Rc<V>>) {
+        self.forget_hash();
         match iter.next() {
             None => {
-                let result = self.terminal.take().map(|rc| (*rc).clone());
+                let result = self.terminal.take();
                 if result.is_none() {
                     return (false, None);
                 }
@@ -70,28 +270,238 @@ impl<V: Clone> Node<V> {
                 (should_remove, result)
             }
             Some(b) => {
-                if self.branches[*b as usize].is_none() {
-                    return (false, None);
+                let branch = match self.branches[*b as usize].as_mut() {
+                    None => return (false, None),
+                    Some(rc) => rc,
+                };
+                let (should_remove, result) = Rc::make_mut(branch).remove_shared(iter);
+                if !should_remove {
+                    (false, result)
+                } else {
+                    self.branches[*b as usize] = None;
+                    let should_remove = self.terminal.is_none()
+                        && !self.branches.iter().any(|el| el.is_some());
+                    (should_remove, result)
                 }
-                let prev = self.branches[*b as usize].take();
-                match prev {
-                    None => {
-                        unreachable!()
-                    }
-
-                    Some(rc) => {
-                        let mut slight_copy = (*rc).clone();
-                        let (should_remove, result) = slight_copy.remove(iter);
-                        if !should_remove {
-                            self.branches[*b as usize] = Some(Rc::new(slight_copy));
-                            (false, result)
-                        } else {
-                            let should_remove = self.terminal.is_none()
-                                && !self.branches.iter().any(|el| el.is_some());
-                            (should_remove, result)
-                        }
-                    }
+            }
+        }
+    }
+}
+
+impl<V> Node<V> {
+    /// Inserts `entries`, sorted by key with no key twice, under `self`,
+    /// which is at `depth` on the path of each of them. A node shared with
+    /// snapshots is copied once for the whole batch. Returns how many keys
+    /// weren't there before.
+    pub fn insert_sorted(
+        &mut self,
+        entries: &mut [(&[u8], Option<Rc<V>>)],
+        depth: usize,
+    ) -> usize {
+        self.forget_hash();
+        let mut added = 0;
+        let mut start = 0;
+        if let Some((key, v)) = entries.first_mut() {
+            if key.len() == depth {
+                added += self.terminal.replace(v.take().unwrap()).is_none() as usize;
+                start = 1;
+            }
+        }
+        while start < entries.len() {
+            let b = entries[start].0[depth];
+            let end = start + entries[start..].partition_point(|(key, _)| key[depth] == b);
+            let branch = self.branches[b as usize].get_or_insert_with(|| Rc::new(Node::new()));
+            added += Rc::make_mut(branch).insert_sorted(&mut entries[start..end], depth + 1);
+            start = end;
+        }
+        added
+    }
+
+    /// Same as [`Node::insert_sorted`] for removing `keys`. Returns whether
+    /// `self` is left empty, and how many keys were removed.
+    pub fn remove_sorted(&mut self, keys: &[&[u8]], depth: usize) -> (bool, usize) {
+        self.forget_hash();
+        let mut removed = 0;
+        let mut start = 0;
+        if keys.first().is_some_and(|key| key.len() == depth) {
+            removed += self.terminal.take().is_some() as usize;
+            start = 1;
+        }
+        while start < keys.len() {
+            let b = keys[start][depth];
+            let end = start + keys[start..].partition_point(|key| key[depth] == b);
+            if let Some(branch) = self.branches[b as usize].as_mut() {
+                let branch = Rc::make_mut(branch);
+                let (should_remove, n) = branch.remove_sorted(&keys[start..end], depth + 1);
+                if should_remove {
+                    self.branches[b as usize] = None;
                 }
+                removed += n;
+            }
+            start = end;
+        }
+        let should_remove =
+            self.terminal.is_none() && !self.branches.iter().any(|el| el.is_some());
+        (should_remove, removed)
+    }
+}
+
+/// Builds a trie bottom-up from keys in ascending order, allocating each
+/// node once and never copying it.
+pub struct Builder<V> {
+    // nodes on the path of the last key, root first, none of them shared
+    stack: Vec<Rc<Node<V>>>,
+    path: Vec<u8>,
+}
+
+impl<V> Builder<V> {
+    pub fn new() -> Self {
+        Builder {
+            stack: vec![Rc::new(Node::new())],
+            path: vec![],
+        }
+    }
+
+    fn top(&mut self) -> &mut Node<V> {
+        Rc::get_mut(self.stack.last_mut().unwrap()).unwrap()
+    }
+
+    /// Adds `key`, replacing the value of the last key if equal to it.
+    /// Panics if `key` comes before the last key.
+    pub fn push(&mut self, key: &[u8], v: V) {
+        assert!(key >= self.path.as_slice(), "keys aren't in ascending order");
+        let common = key
+            .iter()
+            .zip(&self.path)
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.close(common);
+        for b in &key[common..] {
+            self.stack.push(Rc::new(Node::new()));
+            self.path.push(*b);
+        }
+        self.top().terminal = Some(Rc::new(v));
+    }
+
+    /// Attaches the nodes below `depth` on the path to their parents.
+    fn close(&mut self, depth: usize) {
+        while self.path.len() > depth {
+            let node = self.stack.pop().unwrap();
+            let b = self.path.pop().unwrap();
+            self.top().branches[b as usize] = Some(node);
+        }
+    }
+
+    pub fn finish(mut self) -> Node<V> {
+        self.close(0);
+        let root = self.stack.pop().unwrap();
+        Rc::try_unwrap(root).unwrap_or_else(|_| unreachable!())
+    }
+}
+
+impl<V: Clone> Node<V> {
+    /// Values of all keys, in byte order.
+    pub fn into_values(self) -> Vec<(Vec<u8>, V)> {
+        let mut values = vec![];
+        self.into_each(&mut vec![], &mut |path, v| values.push((path.to_vec(), into_owned(v))));
+        values
+    }
+
+    pub fn insert(&mut self, iter: Iter<'_, u8>, v: V) -> Option<V> {
+        self.insert_shared(iter, Rc::new(v)).map(into_owned)
+    }
+
+    pub fn remove(&mut self, iter: Iter<'_, u8>) -> (bool, Option<V>) {
+        let (should_remove, result) = self.remove_shared(iter);
+        (should_remove, result.map(into_owned))
+    }
+
+    /// Follows `iter` for as long as the trie has the branches, copying nodes
+    /// shared with snapshots on the way. Returns the deepest node reached
+    /// and the number of bytes consumed.
+    pub fn descend_mut(&mut self, iter: Iter<'_, u8>) -> (&mut Node<V>, usize) {
+        let mut node = self;
+        node.forget_hash();
+        let mut depth = 0;
+        for b in iter {
+            if node.branches[*b as usize].is_none() {
+                break;
+            }
+            node = Rc::make_mut(node.branches[*b as usize].as_mut().unwrap());
+            node.forget_hash();
+            depth += 1;
+        }
+        (node, depth)
+    }
+
+    /// Same as [`Node::descend_mut`], creating the missing nodes, so that
+    /// the whole of `iter` is consumed.
+    pub fn grow(&mut self, iter: Iter<'_, u8>) -> &mut Node<V> {
+        let mut node = self;
+        node.forget_hash();
+        for b in iter {
+            let branch =
+                node.branches[*b as usize].get_or_insert_with(|| Rc::new(Node::new()));
+            node = Rc::make_mut(branch);
+            node.forget_hash();
+        }
+        node
+    }
+
+    pub fn terminal_mut(&mut self) -> Option<&mut V> {
+        self.forget_hash();
+        self.terminal.as_mut().map(Rc::make_mut)
+    }
+
+    pub fn terminal_or_insert_with<F: FnOnce() -> V>(&mut self, f: F) -> &mut V {
+        self.forget_hash();
+        Rc::make_mut(self.terminal.get_or_insert_with(|| Rc::new(f())))
+    }
+}
+
+impl<V: AsFromBytes> Node<V> {
+    /// Merkle hash of the trie rooted at `self`, see [`merkle`].
+    pub fn hash(&self) -> Hash {
+        if let Some(hash) = self.hash.get() {
+            return hash;
+        }
+        let terminal = self.terminal_hash();
+        let branches = self.branches_hashed(None);
+        let hash = merkle::node_hash(terminal.as_ref(), branches.iter().map(|(b, h)| (*b, h)));
+        self.hash.set(Some(hash));
+        hash
+    }
+
+    pub fn terminal_hash(&self) -> Option<Hash> {
+        let v = self.terminal.as_ref()?;
+        Some(merkle::value_hash(v.as_bytes()))
+    }
+
+    /// Hashes of the branches by byte, but the one of `except`.
+    pub fn branches_hashed(&self, except: Option<u8>) -> Vec<(u8, Hash)> {
+        self.branches
+            .iter()
+            .enumerate()
+            .filter(|(b, _)| Some(*b as u8) != except)
+            .filter_map(|(b, branch)| branch.as_ref().map(|node| (b as u8, node.hash())))
+            .collect()
+    }
+
+    /// The nodes on the path of `key`, as far as it goes, with what it
+    /// takes to hash them.
+    pub fn prove(&self, key: &[u8]) -> Vec<Level> {
+        let mut levels = vec![];
+        let mut node = self;
+        let mut iter = key.iter();
+        loop {
+            let next = iter.next().copied();
+            levels.push(Level {
+                terminal: node.terminal_hash(),
+                branches: node.branches_hashed(next),
+            });
+            match next.and_then(|b| node.branches[b as usize].as_ref()) {
+                Some(branch) => node = branch,
+                None => return levels,
             }
         }
     }
@@ -0,0 +1,457 @@
+//! Bringing a [`VMapTree`] to the state of another one, or of one of its
+//! snapshots, by comparing node hashes level by level and only
+//! descending into the subtrees that differ.
+//!
+//! The side being updated drives with a [`Reconciler`], sending its
+//! [`Request`]s to the other side's [`Responder`] over any transport, as
+//! bytes with `write_to` and `read_from` if need be:
+//!
+//! ```
+//! use tt::treelike::sync::Reconciler;
+//! use tt::treelike::VMapTree;
+//! use tt::VersionedMap;
+//!
+//! let mut source = VMapTree::<String, String>::new();
+//! source.insert("key".to_owned(), "value".to_owned());
+//! let mut replica = VMapTree::<String, String>::new();
+//!
+//! let mut reconciler = Reconciler::new();
+//! while let Some(request) = reconciler.request(&replica) {
+//!     let response = source.responder().respond(&request);
+//!     reconciler.receive(&mut replica, &response).unwrap();
+//! }
+//! assert_eq!(source.root_hash(), replica.root_hash());
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+use super::as_bytes::AsFromBytes;
+use super::node::Node;
+use super::VMapTree;
+use crate::persist::{invalid, read_bytes, read_len, write_bytes, write_len};
+use crate::Hash;
+
+/// Nodes the reconciling side wants to compare, by path from the root,
+/// each with the hash of the value it holds there, if any.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    nodes: Vec<(Vec<u8>, Option<Hash>)>,
+}
+
+/// Value of a requested node, as it compares to the one of the requester.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Terminal {
+    Same,
+    Absent,
+    Value(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reply {
+    terminal: Terminal,
+    branches: Vec<(u8, Hash)>,
+}
+
+/// The requested nodes, `None` for the ones missing, and the root hash
+/// of the state they come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    root: Hash,
+    nodes: Vec<Option<Reply>>,
+}
+
+/// Answers [`Request`]s about a state of a [`VMapTree`], obtained with
+/// [`VMapTree::responder`] or [`VMapTree::snapshot_responder`].
+pub struct Responder<'a, V> {
+    root: &'a Node<V>,
+}
+
+impl<V: AsFromBytes> Responder<'_, V> {
+    pub fn respond(&self, request: &Request) -> Response {
+        let nodes = request
+            .nodes
+            .iter()
+            .map(|(path, known)| {
+                let node = self.root.descend(path.iter())?;
+                let terminal = match (node.terminal_hash(), node.terminal()) {
+                    (hash, _) if hash == *known => Terminal::Same,
+                    (_, Some(v)) => Terminal::Value(v.as_bytes().to_vec()),
+                    (_, None) => Terminal::Absent,
+                };
+                Some(Reply {
+                    terminal,
+                    branches: node.branches_hashed(None),
+                })
+            })
+            .collect();
+        Response {
+            root: self.root.hash(),
+            nodes,
+        }
+    }
+}
+
+impl<K, V> VMapTree<K, V> {
+    /// Answers the requests of a [`Reconciler`] syncing to the live state.
+    pub fn responder(&self) -> Responder<'_, V> {
+        Responder { root: &self.root }
+    }
+
+    /// Same as [`VMapTree::responder`] for the snapshot `tag`.
+    pub fn snapshot_responder(&self, tag: &str) -> Option<Responder<'_, V>> {
+        let root = self.snapshots.get(tag)?;
+        Some(Responder { root })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncError {
+    /// The response doesn't answer the request.
+    Malformed,
+    /// A value doesn't read back as the type of the map.
+    Unreadable,
+    /// The state synced to changed along the way, or the map doesn't end
+    /// up hashing like it.
+    Diverged,
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::Malformed => write!(f, "response doesn't match the request"),
+            SyncError::Unreadable => write!(f, "unreadable value"),
+            SyncError::Diverged => write!(f, "state changed while syncing"),
+        }
+    }
+}
+
+impl Error for SyncError {}
+
+/// Walks the live state of a map towards the one a [`Responder`]
+/// answers for, one level of the trie per round trip.
+#[derive(Debug, Clone)]
+pub struct Reconciler {
+    // paths of the nodes to compare next
+    pending: Vec<Vec<u8>>,
+    root: Option<Hash>,
+    nodes_received: usize,
+    values_received: usize,
+}
+
+impl Default for Reconciler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reconciler {
+    pub fn new() -> Self {
+        Reconciler {
+            pending: vec![vec![]],
+            root: None,
+            nodes_received: 0,
+            values_received: 0,
+        }
+    }
+
+    /// Number of nodes compared so far.
+    pub fn nodes_received(&self) -> usize {
+        self.nodes_received
+    }
+
+    /// Number of values transferred so far.
+    pub fn values_received(&self) -> usize {
+        self.values_received
+    }
+
+    /// What to ask next, `None` once `map` is synced.
+    pub fn request<K, V: AsFromBytes>(&self, map: &VMapTree<K, V>) -> Option<Request> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let nodes = self
+            .pending
+            .iter()
+            .map(|path| {
+                let known = map.root.descend(path.iter()).and_then(Node::terminal_hash);
+                (path.clone(), known)
+            })
+            .collect();
+        Some(Request { nodes })
+    }
+
+    /// Updates `map` with the answer to the last [`Reconciler::request`].
+    /// On error `map` is left partially synced.
+    pub fn receive<K, V>(
+        &mut self,
+        map: &mut VMapTree<K, V>,
+        response: &Response,
+    ) -> Result<(), SyncError>
+    where
+        V: AsFromBytes,
+    {
+        if response.nodes.len() != self.pending.len() {
+            return Err(SyncError::Malformed);
+        }
+        if *self.root.get_or_insert(response.root) != response.root {
+            return Err(SyncError::Diverged);
+        }
+        let mut next = vec![];
+        for (path, reply) in self.pending.iter().zip(&response.nodes) {
+            let reply = match reply {
+                Some(reply) => reply,
+                None => {
+                    detach(&mut map.root, path);
+                    continue;
+                }
+            };
+            self.nodes_received += 1;
+            match &reply.terminal {
+                Terminal::Same => {}
+                Terminal::Absent if path.is_empty() => return Err(SyncError::Malformed),
+                Terminal::Absent => {
+                    map.root.remove_shared(path.iter());
+                }
+                Terminal::Value(_) if path.is_empty() => return Err(SyncError::Malformed),
+                Terminal::Value(bytes) => {
+                    let v = V::read_from(bytes).ok_or(SyncError::Unreadable)?;
+                    map.root.insert_shared(path.iter(), Rc::new(v));
+                    self.values_received += 1;
+                }
+            }
+            let local = match map.root.descend(path.iter()) {
+                Some(node) => node.branches_hashed(None),
+                None => vec![],
+            };
+            for (b, _) in &local {
+                if !reply.branches.iter().any(|(other, _)| other == b) {
+                    detach(&mut map.root, &[&path[..], &[*b]].concat());
+                }
+            }
+            for (b, hash) in &reply.branches {
+                if !local.contains(&(*b, *hash)) {
+                    next.push([&path[..], &[*b]].concat());
+                }
+            }
+        }
+        self.pending = next;
+        if self.pending.is_empty() && map.root.hash() != response.root {
+            return Err(SyncError::Diverged);
+        }
+        Ok(())
+    }
+}
+
+fn detach<V>(root: &mut Node<V>, path: &[u8]) {
+    if path.is_empty() {
+        *root = Node::new();
+    } else {
+        root.attach(path.iter(), None);
+    }
+}
+
+const SAME: u8 = 0;
+const ABSENT: u8 = 1;
+const VALUE: u8 = 2;
+
+fn read_hash<R: Read>(input: &mut R) -> io::Result<Hash> {
+    let mut hash = [0; 32];
+    input.read_exact(&mut hash)?;
+    Ok(hash)
+}
+
+fn read_byte<R: Read>(input: &mut R) -> io::Result<u8> {
+    let mut byte = [0; 1];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+impl Request {
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write_len(&mut out, self.nodes.len())?;
+        for (path, known) in &self.nodes {
+            write_bytes(&mut out, path)?;
+            match known {
+                Some(hash) => {
+                    out.write_all(&[1])?;
+                    out.write_all(hash)?;
+                }
+                None => out.write_all(&[0])?,
+            }
+        }
+        out.flush()
+    }
+
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<Request> {
+        let mut nodes = vec![];
+        for _ in 0..read_len(&mut input)? {
+            let path = read_bytes(&mut input)?;
+            let known = match read_byte(&mut input)? {
+                0 => None,
+                1 => Some(read_hash(&mut input)?),
+                _ => return Err(invalid("malformed request")),
+            };
+            nodes.push((path, known));
+        }
+        Ok(Request { nodes })
+    }
+}
+
+impl Response {
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(&self.root)?;
+        write_len(&mut out, self.nodes.len())?;
+        for reply in &self.nodes {
+            let reply = match reply {
+                Some(reply) => reply,
+                None => {
+                    out.write_all(&[0])?;
+                    continue;
+                }
+            };
+            out.write_all(&[1])?;
+            match &reply.terminal {
+                Terminal::Same => out.write_all(&[SAME])?,
+                Terminal::Absent => out.write_all(&[ABSENT])?,
+                Terminal::Value(bytes) => {
+                    out.write_all(&[VALUE])?;
+                    write_bytes(&mut out, bytes)?;
+                }
+            }
+            write_len(&mut out, reply.branches.len())?;
+            for (b, hash) in &reply.branches {
+                out.write_all(&[*b])?;
+                out.write_all(hash)?;
+            }
+        }
+        out.flush()
+    }
+
+    pub fn read_from<R: Read>(mut input: R) -> io::Result<Response> {
+        let root = read_hash(&mut input)?;
+        let mut nodes = vec![];
+        for _ in 0..read_len(&mut input)? {
+            if read_byte(&mut input)? == 0 {
+                nodes.push(None);
+                continue;
+            }
+            let terminal = match read_byte(&mut input)? {
+                SAME => Terminal::Same,
+                ABSENT => Terminal::Absent,
+                VALUE => Terminal::Value(read_bytes(&mut input)?),
+                _ => return Err(invalid("malformed response")),
+            };
+            let mut branches = vec![];
+            for _ in 0..read_len(&mut input)? {
+                branches.push((read_byte(&mut input)?, read_hash(&mut input)?));
+            }
+            nodes.push(Some(Reply { terminal, branches }));
+        }
+        Ok(Response { root, nodes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reconciler, Request, Responder, Response, SyncError};
+    use crate::treelike::VMapTree;
+    use crate::VersionedMap;
+
+    type Map = VMapTree<String, String>;
+
+    fn filled(n: usize) -> Map {
+        let mut map = VMapTree::new();
+        for i in 0..n {
+            map.insert(format!("key/{}", i), format!("value {}", i));
+        }
+        map
+    }
+
+    // goes through bytes, as over an actual transport
+    fn sync(replica: &mut Map, responder: &Responder<'_, String>) -> Reconciler {
+        let mut reconciler = Reconciler::new();
+        while let Some(request) = reconciler.request(replica) {
+            let mut bytes = vec![];
+            request.write_to(&mut bytes).unwrap();
+            let response = responder.respond(&Request::read_from(&bytes[..]).unwrap());
+            let mut bytes = vec![];
+            response.write_to(&mut bytes).unwrap();
+            let response = Response::read_from(&bytes[..]).unwrap();
+            reconciler.receive(replica, &response).unwrap();
+        }
+        reconciler
+    }
+
+    fn contents(map: &Map) -> Vec<(String, String)> {
+        map.entries()
+            .into_iter()
+            .map(|(k, v)| (k, v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn only_differing_values_are_transferred() {
+        let source = filled(1000);
+        let mut replica = filled(1000);
+        replica.insert("key/5".to_owned(), "stale".to_owned());
+        replica.remove(&"key/999".to_owned());
+        replica.insert("key/1000".to_owned(), "extra".to_owned());
+        replica.insert("other".to_owned(), "extra".to_owned());
+
+        let reconciler = sync(&mut replica, &source.responder());
+        assert_eq!(contents(&source), contents(&replica));
+        assert_eq!(source.root_hash(), replica.root_hash());
+        assert_eq!(2, reconciler.values_received());
+        assert!(reconciler.nodes_received() < 30);
+
+        let reconciler = sync(&mut replica, &source.responder());
+        assert_eq!(1, reconciler.nodes_received());
+    }
+
+    #[test]
+    fn syncs_to_snapshots_and_empty_maps() {
+        let mut source = filled(10);
+        source.checkpoint("ONE".to_owned());
+        source.insert("key/11".to_owned(), "new".to_owned());
+
+        let mut replica = filled(3);
+        sync(&mut replica, &source.snapshot_responder("ONE").unwrap());
+        assert_eq!(contents(&filled(10)), contents(&replica));
+        assert!(source.snapshot_responder("TWO").is_none());
+
+        sync(&mut replica, &Map::new().responder());
+        assert!(replica.entries().is_empty());
+        assert_eq!(Map::new().root_hash(), replica.root_hash());
+    }
+
+    #[test]
+    fn changing_source_is_detected() {
+        let mut source = filled(10);
+        let mut replica = Map::new();
+        let mut reconciler = Reconciler::new();
+
+        let request = reconciler.request(&replica).unwrap();
+        let response = source.responder().respond(&request);
+        reconciler.receive(&mut replica, &response).unwrap();
+
+        source.insert("key/1".to_owned(), "changed".to_owned());
+        let request = reconciler.request(&replica).unwrap();
+        let response = source.responder().respond(&request);
+        assert_eq!(
+            Err(SyncError::Diverged),
+            reconciler.receive(&mut replica, &response)
+        );
+
+        let response = Response {
+            root: response.root,
+            nodes: vec![],
+        };
+        assert_eq!(
+            Err(SyncError::Malformed),
+            Reconciler::new().receive(&mut replica, &response)
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+
+use super::Version;
+
+/// Versions held inline before a history moves to a B-tree.
+const INLINE: usize = 4;
+
+/// The versions of a single key, sorted. Most keys only ever have a
+/// couple, which are kept inline rather than in a B-tree, whose smallest
+/// node already has room for eleven.
+#[derive(Clone)]
+pub struct History<V>(Repr<V>);
+
+#[derive(Clone)]
+enum Repr<V> {
+    // filled from the front
+    Inline([Option<(Version, Option<V>)>; INLINE]),
+    Tree(BTreeMap<Version, Option<V>>),
+}
+
+impl<V> History<V> {
+    pub(super) fn new() -> Self {
+        History(Repr::Inline([None, None, None, None]))
+    }
+
+    /// Entries stored outside the history itself.
+    pub(super) fn heap_entries(&self) -> usize {
+        match &self.0 {
+            Repr::Inline(_) => 0,
+            Repr::Tree(tree) => tree.len(),
+        }
+    }
+
+    pub(super) fn versions(&self) -> Box<dyn Iterator<Item = &Version> + '_> {
+        match &self.0 {
+            Repr::Inline(entries) => {
+                Box::new(entries.iter().flatten().map(|(vers, _)| vers))
+            }
+            Repr::Tree(tree) => Box::new(tree.keys()),
+        }
+    }
+
+    /// The entry of the greatest version not above `version`.
+    pub(super) fn floor(&self, version: u64) -> Option<(&Version, &Option<V>)> {
+        match &self.0 {
+            Repr::Inline(entries) => entries
+                .iter()
+                .flatten()
+                .take_while(|(vers, _)| vers.num() <= version)
+                .last()
+                .map(|(vers, val)| (vers, val)),
+            Repr::Tree(tree) => tree.range(..=Version::Actual(version)).next_back(),
+        }
+    }
+
+    pub(super) fn floor_mut(&mut self, version: u64) -> Option<(&Version, &mut Option<V>)> {
+        match &mut self.0 {
+            Repr::Inline(entries) => entries
+                .iter_mut()
+                .flatten()
+                .take_while(|(vers, _)| vers.num() <= version)
+                .last()
+                .map(|(vers, val)| (&*vers, val)),
+            Repr::Tree(tree) => tree.range_mut(..=Version::Actual(version)).next_back(),
+        }
+    }
+
+    pub(super) fn get_mut(&mut self, version: u64) -> Option<&mut Option<V>> {
+        match self.floor_mut(version) {
+            Some((vers, val)) if vers.num() == version => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Same as [`BTreeMap::insert`], moving to a B-tree when full.
+    pub(super) fn insert(&mut self, vers: Version, val: Option<V>) -> Option<Option<V>> {
+        let entries = match &mut self.0 {
+            Repr::Tree(tree) => return tree.insert(vers, val),
+            Repr::Inline(entries) => entries,
+        };
+        let len = entries.iter().flatten().count();
+        let at = entries[..len]
+            .partition_point(|entry| matches!(entry, Some((v, _)) if *v < vers));
+        if let Some(Some((v, old))) = entries.get_mut(at) {
+            if *v == vers {
+                return Some(std::mem::replace(old, val));
+            }
+        }
+        if len == INLINE {
+            let mut tree: BTreeMap<_, _> =
+                entries.iter_mut().flat_map(Option::take).collect();
+            tree.insert(vers, val);
+            self.0 = Repr::Tree(tree);
+            return None;
+        }
+        entries[len] = Some((vers, val));
+        entries[at..=len].rotate_right(1);
+        None
+    }
+}
+
+#[cfg(test)]
+mod history_storage_tests {
+    use super::{History, Repr, INLINE};
+    use crate::treelike_no_trie::Version;
+
+    #[test]
+    fn moves_to_a_tree_past_the_inline_capacity() {
+        let mut history = History::new();
+        for num in [6, 2, 4, 0] {
+            assert_eq!(None, history.insert(Version::Actual(num), Some(num)));
+        }
+        assert!(matches!(history.0, Repr::Inline(_)));
+        assert_eq!(Some(Some(4)), history.insert(Version::Actual(4), None));
+        assert_eq!(INLINE, history.versions().count());
+        assert_eq!(0, history.heap_entries());
+
+        history.insert(
+            Version::Link {
+                linked_to: 2,
+                this: 5,
+            },
+            None,
+        );
+        assert!(matches!(history.0, Repr::Tree(_)));
+        assert_eq!(INLINE + 1, history.heap_entries());
+        history.insert(Version::Actual(7), None);
+        let nums: Vec<u64> = history.versions().map(Version::num).collect();
+        assert_eq!(vec![0, 2, 4, 5, 6, 7], nums);
+    }
+
+    #[test]
+    fn floor_finds_the_greatest_version_not_above() {
+        for entries in [3, 10] {
+            let mut history = History::new();
+            for num in 1..=entries {
+                history.insert(Version::Actual(num * 10), Some(num));
+            }
+            assert!(history.floor(9).is_none());
+            assert_eq!(Some(&Some(1)), history.floor(10).map(|(_, val)| val));
+            assert_eq!(Some(&Some(2)), history.floor(29).map(|(_, val)| val));
+            assert_eq!(
+                Some(&Some(entries)),
+                history.floor(u64::MAX).map(|(_, val)| val)
+            );
+
+            *history.get_mut(20).unwrap() = None;
+            assert_eq!(Some(&None), history.floor(25).map(|(_, val)| val));
+            assert!(history.get_mut(25).is_none());
+        }
+    }
+}
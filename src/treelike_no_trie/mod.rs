@@ -1,12 +1,25 @@
 use std::cmp::{Ord, Ordering};
-use std::collections::{BTreeMap, HashMap};
-use std::hash::Hash;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Index;
+
+mod history;
+pub mod sorted;
+mod store;
+
+use history::History;
+use store::{Slot, Store};
 
 #[allow(unused)]
 #[derive(Eq, Clone, Copy)]
 enum Version {
     Actual(u64),
     Link { linked_to: u64, this: u64 },
+    /// Only seen at `this`, a version checkpointed without ever being
+    /// live; any later version sees the one before it instead.
+    Detached(u64),
 }
 
 impl Version {
@@ -14,6 +27,7 @@ impl Version {
         match self {
             Self::Actual(num) => *num,
             Self::Link { this, .. } => *this,
+            Self::Detached(this) => *this,
         }
     }
 }
@@ -36,92 +50,329 @@ impl PartialEq for Version {
     }
 }
 
+/// Position of a frozen state in the linear sequence of states of a
+/// [`VMapNoTrie`]; every checkpoint and rollback advances it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VersionId(u64);
+
 #[allow(unused)]
 type VersionedMapTreelikeNoTrie<K, V> = VMapNoTrie<K, V>;
 
-#[allow(unused)]
-struct VMapNoTrie<K, V> {
+/// Keeps the history of every key in `S`, a hash map unless it's a
+/// [`VMapNoTrieSorted`](sorted::VMapNoTrieSorted).
+#[derive(Clone)]
+pub struct VMapNoTrie<K, V, S = HashMap<K, History<V>>> {
     current_ver: u64,
-    state: HashMap<K, BTreeMap<Version, Option<V>>>,
+    state: S,
     snapshots: HashMap<String, Version>,
+
+    _phantom_data: PhantomData<(K, V)>,
 }
 
 const ABSOLUTE_FIRST_VERSION: u64 = 0;
 
 impl<K, V> VMapNoTrie<K, V> {
-    fn new() -> Self {
+    pub fn new() -> Self {
+        VMapNoTrie::with_store()
+    }
+}
+
+impl<K, V, S: Default> VMapNoTrie<K, V, S> {
+    fn with_store() -> Self {
         Self {
             current_ver: 1,
-            state: HashMap::new(),
+            state: S::default(),
             snapshots: HashMap::new(),
+            _phantom_data: PhantomData,
         }
     }
+}
 
-    fn get_version_mut(
-        v_map: &mut BTreeMap<Version, Option<V>>,
-        mut version: u64,
-    ) -> (&Version, &mut Option<V>) {
-        loop {
-            let (vers, _) = v_map
-                .range(..=Version::Actual(version))
-                .rev()
-                .next()
-                .unwrap();
-            if let Version::Link { linked_to, .. } = *vers {
-                version = linked_to;
-                continue;
-            } else {
-                break;
+/// History of a key first written at the current version.
+fn new_history<V>() -> History<V> {
+    let mut v_map = History::new();
+    v_map.insert(Version::Actual(ABSOLUTE_FIRST_VERSION), None);
+    v_map
+}
+
+fn get_version_mut<V>(v_map: &mut History<V>, mut version: u64) -> (&Version, &mut Option<V>) {
+    loop {
+        match v_map.floor(version) {
+            Some((Version::Link { linked_to, .. }, _)) => version = *linked_to,
+            Some((Version::Detached(this), _)) if *this != version => version = this - 1,
+            _ => return v_map.floor_mut(version).unwrap(),
+        }
+    }
+}
+
+/// Slot of the current version in `v_map`, copied forward from
+/// the version it is inherited from if there is none yet.
+fn current_slot<V: Clone>(v_map: &mut History<V>, current_ver: u64) -> &mut Option<V> {
+    let (v_prev, val) = get_version_mut(v_map, current_ver);
+    if v_prev.num() != current_ver {
+        let copy = val.clone();
+        v_map.insert(Version::Actual(current_ver), copy);
+    }
+    v_map.get_mut(current_ver).unwrap()
+}
+
+fn get_version<V>(v_map: &History<V>, mut version: u64) -> (&Version, &Option<V>) {
+    loop {
+        match v_map.floor(version).unwrap() {
+            (Version::Link { linked_to, .. }, _) => version = *linked_to,
+            (Version::Detached(this), _) if *this != version => version = this - 1,
+            found => return found,
+        }
+    }
+}
+
+impl<K, V, S> VMapNoTrie<K, V, S> {
+    /// Version `tag` was checkpointed at.
+    pub fn version_of(&self, tag: &str) -> Option<VersionId> {
+        self.snapshots.get(tag).map(|vers| VersionId(vers.num()))
+    }
+
+    /// Freezes the current state without tagging it, returning the
+    /// version it can later be addressed by.
+    pub fn freeze(&mut self) -> VersionId {
+        let version = VersionId(self.current_ver);
+        self.current_ver += 1;
+        version
+    }
+
+    /// Tags the current state with `tag` and freezes it, returning the
+    /// version it can later be addressed by.
+    pub fn checkpoint_versioned(&mut self, tag: String) -> VersionId {
+        let version = self.freeze();
+        self.snapshots.insert(tag, Version::Actual(version.0));
+        version
+    }
+}
+
+impl<K, V, S> VMapNoTrie<K, V, S>
+where
+    S: Store<K, V>,
+{
+    /// Every value `k` has held, oldest first, as `(version, value)` pairs.
+    /// `None` marks the key being absent; rollback links are resolved to
+    /// the value they point at. The last pair may be of the live state,
+    /// whose version can't be read or rolled back to until it's frozen. Values only given to a checkpoint by
+    /// [`checkpoint_with`](crate::VersionedMap::checkpoint_with) were never
+    /// held and are left out.
+    pub fn history(&self, k: &K) -> Vec<(VersionId, Option<&V>)> {
+        match self.state.get(k) {
+            None => vec![],
+            Some(v_map) => v_map
+                .versions()
+                .filter(|vers| vers.num() != ABSOLUTE_FIRST_VERSION)
+                .filter(|vers| !matches!(vers, Version::Detached(_)))
+                .map(|vers| {
+                    let (_, val) = get_version(v_map, vers.num());
+                    (VersionId(vers.num()), val.as_ref())
+                })
+                .collect(),
+        }
+    }
+
+    /// Tags of the checkpoints at which `k` was mapped to `value`,
+    /// in checkpoint order.
+    pub fn tags_where(&self, k: &K, value: &V) -> Vec<&str>
+    where
+        V: PartialEq,
+    {
+        let v_map = match self.state.get(k) {
+            None => return vec![],
+            Some(v_map) => v_map,
+        };
+        let mut tags: Vec<(u64, &str)> = self
+            .snapshots
+            .iter()
+            .filter(|(_, vers)| {
+                let (_, val) = get_version(v_map, vers.num());
+                val.as_ref() == Some(value)
+            })
+            .map(|(tag, vers)| (vers.num(), tag.as_str()))
+            .collect();
+        tags.sort_unstable();
+        tags.into_iter().map(|(_, tag)| tag).collect()
+    }
+
+    /// Value of `k` as it was at `version`, `None` unless `version` is
+    /// frozen.
+    pub fn get_at_version(&self, version: VersionId, k: &K) -> Option<&V> {
+        if version.0 >= self.current_ver {
+            return None;
+        }
+        self.get_at(version.0, k)
+    }
+
+    fn get_live(&self, k: &K) -> Option<&V> {
+        self.get_at(self.current_ver, k)
+    }
+
+    fn get_at(&self, version: u64, k: &K) -> Option<&V> {
+        match self.state.get(k) {
+            None => None,
+            Some(v_map) => {
+                let (_, val) = get_version(v_map, version);
+                val.as_ref()
             }
         }
-        v_map
-            .range_mut(..=Version::Actual(version))
-            .rev()
-            .next()
-            .unwrap()
-    }
-    fn get_version(
-        v_map: &BTreeMap<Version, Option<V>>,
-        mut version: u64,
-    ) -> (&Version, &Option<V>) {
-        loop {
-            let (vers, _) = v_map
-                .range(..=Version::Actual(version))
-                .rev()
-                .next()
-                .unwrap();
-            if let Version::Link { linked_to, .. } = *vers {
-                version = linked_to;
-                continue;
-            } else {
-                break;
+    }
+
+    /// Same as [`rollback`](crate::VersionedMap::rollback), addressing the
+    /// target state by version instead of by tag, which has to be frozen.
+    pub fn rollback_to_version(&mut self, version: VersionId) -> bool {
+        if version.0 >= self.current_ver {
+            return false;
+        }
+        self.link_to(version.0);
+        true
+    }
+
+    pub fn entry(&mut self, k: K) -> Entry<'_, K, V, S> {
+        Entry {
+            inner: self.state.entry(k),
+            current_ver: self.current_ver,
+        }
+    }
+
+    /// Applies `f` to the value of `k` in place, returns whether `k` was present.
+    pub fn update<F: FnOnce(&mut V)>(&mut self, k: &K, f: F) -> bool
+    where
+        V: Clone,
+    {
+        let current_ver = self.current_ver;
+        match self.state.get_mut(k) {
+            Some(v_map) if get_version(v_map, current_ver).1.is_some() => {
+                let slot = current_slot(v_map, current_ver);
+                f(slot.as_mut().unwrap());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn link_to(&mut self, found: u64) {
+        self.current_ver += 1;
+        for v_map in self.state.values_mut() {
+            v_map.insert(
+                Version::Link {
+                    linked_to: found,
+                    this: self.current_ver,
+                },
+                None,
+            );
+        }
+        self.current_ver += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub keys: usize,
+    pub snapshots: usize,
+    /// values stored across all keys' version chains, absent ones included
+    pub version_entries: usize,
+    /// rollback links stored across all keys' version chains
+    pub link_entries: usize,
+    /// approximate, not counting memory owned by keys and values
+    pub heap_bytes: usize,
+}
+
+impl<K, V, S> VMapNoTrie<K, V, S>
+where
+    S: Store<K, V>,
+{
+    pub fn stats(&self) -> Stats {
+        let mut keys = 0;
+        let mut version_entries = 0;
+        let mut link_entries = 0;
+        let mut heap_entries = 0;
+        for (_, v_map) in self.state.iter() {
+            let (_, val) = get_version(v_map, self.current_ver);
+            keys += val.iter().count();
+            heap_entries += v_map.heap_entries();
+            for vers in v_map.versions() {
+                match vers {
+                    Version::Actual(_) | Version::Detached(_) => version_entries += 1,
+                    Version::Link { .. } => link_entries += 1,
+                }
             }
         }
-        v_map
-            .range(..=Version::Actual(version))
-            .rev()
-            .next()
-            .unwrap()
+        let entry_bytes = mem::size_of::<(Version, Option<V>)>();
+        let tags_bytes: usize = self.snapshots.keys().map(String::capacity).sum();
+        Stats {
+            keys,
+            snapshots: self.snapshots.len(),
+            version_entries,
+            link_entries,
+            heap_bytes: self.state.heap_bytes()
+                + heap_entries * entry_bytes
+                + crate::hash_map_bytes(&self.snapshots)
+                + tags_bytes,
+        }
     }
 }
 
-impl<K, V> super::VersionedMap<K, V> for VMapNoTrie<K, V>
+/// A view into a single key of a [`VMapNoTrie`], obtained with
+/// [`VMapNoTrie::entry`].
+pub struct Entry<'a, K, V, S = HashMap<K, History<V>>>
+where
+    S: Store<K, V> + 'a,
+{
+    inner: S::Entry<'a>,
+    current_ver: u64,
+}
+
+impl<'a, K, V, S> Entry<'a, K, V, S>
 where
-    K: Eq + Hash + Clone,
     V: Clone,
+    S: Store<K, V> + 'a,
+{
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        self.or_insert_with(|| default)
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, f: F) -> &'a mut V {
+        let v_map = self.inner.or_insert_with(new_history);
+        current_slot(v_map, self.current_ver).get_or_insert_with(f)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Some(v_map) = self.inner.get_mut() {
+            if get_version(v_map, self.current_ver).1.is_some() {
+                let slot = current_slot(v_map, self.current_ver);
+                f(slot.as_mut().unwrap());
+            }
+        }
+        self
+    }
+}
+
+/// [`entries`](crate::VersionedMap::entries) are in key order for a
+/// [`VMapNoTrieSorted`](sorted::VMapNoTrieSorted), in no particular order
+/// otherwise.
+impl<K, V, S> super::VersionedMap<K, V> for VMapNoTrie<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Store<K, V>,
 {
     fn insert(&mut self, k: K, v: V) -> Option<V> {
         match self.state.get_mut(&k) {
             None => {
-                let mut v_map = BTreeMap::new();
-                v_map.insert(Version::Actual(ABSOLUTE_FIRST_VERSION), None);
+                let mut v_map = new_history();
                 v_map.insert(Version::Actual(self.current_ver), Some(v));
                 self.state.insert(k, v_map);
                 None
             }
             Some(v_map) => {
-                let (v_prev, val) =
-                    VMapNoTrie::<K, V>::get_version_mut(v_map, self.current_ver);
+                let (v_prev, val) = get_version_mut(v_map, self.current_ver);
                 if v_prev.num() == self.current_ver {
                     val.replace(v)
                 } else {
@@ -136,7 +387,7 @@ where
         match self.state.get(k) {
             None => None,
             Some(v_map) => {
-                let (_, val) = VMapNoTrie::<K, V>::get_version(v_map, self.current_ver);
+                let (_, val) = get_version(v_map, self.current_ver);
                 val.as_ref()
             }
         }
@@ -145,8 +396,7 @@ where
         match self.state.get_mut(k) {
             None => None,
             Some(v_map) => {
-                let (v_prev, val) =
-                    VMapNoTrie::<K, V>::get_version_mut(v_map, self.current_ver);
+                let (v_prev, val) = get_version_mut(v_map, self.current_ver);
                 if v_prev.num() == self.current_ver {
                     val.take()
                 } else {
@@ -159,9 +409,7 @@ where
     }
 
     fn checkpoint(&mut self, tag: String) {
-        self.snapshots
-            .insert(tag, Version::Actual(self.current_ver));
-        self.current_ver += 1;
+        self.checkpoint_versioned(tag);
     }
 
     fn rollback(&mut self, tag: String) -> bool {
@@ -172,26 +420,371 @@ where
                 return false;
             }
         };
-        self.current_ver += 1;
-        for (_, v_map) in &mut self.state {
-            v_map.insert(
-                Version::Link {
-                    linked_to: found,
-                    this: self.current_ver,
-                },
-                None,
-            );
-        }
-        self.current_ver += 1;
+        self.link_to(found);
         true
     }
 
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String> {
+        let found = self.snapshots.get(&tag)?.num();
+        let saved = save_as
+            .unwrap_or_else(|| crate::autosave_tag(|tag| self.snapshots.contains_key(tag)));
+        self.checkpoint_versioned(saved.clone());
+        self.link_to(found);
+        Some(saved)
+    }
+
     fn prune(&mut self) {
         for (_k, _v) in self.snapshots.drain() {}
     }
+
+    fn checkpoint_with(&mut self, tag: String, ops: Vec<(K, Option<V>)>) {
+        // the live state keeps the version frozen first, the tagged one
+        // gets a version of its own that only it sees
+        self.freeze();
+        let version = self.freeze().0;
+        for (k, v) in ops {
+            self.state
+                .entry(k)
+                .or_insert_with(new_history)
+                .insert(Version::Detached(version), v);
+        }
+        self.snapshots.insert(tag, Version::Actual(version));
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self.snapshots.keys().map(String::as_str).collect();
+        tags.sort_unstable();
+        tags
+    }
+
+    fn entries(&self) -> Vec<(K, &V)> {
+        self.state
+            .iter()
+            .filter_map(|(k, v_map)| {
+                let (_, val) = get_version(v_map, self.current_ver);
+                val.as_ref().map(|v| (k.clone(), v))
+            })
+            .collect()
+    }
+}
+impl<K, V, S> Extend<(K, V)> for VMapNoTrie<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Store<K, V>,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            crate::VersionedMap::insert(self, k, v);
+        }
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for VMapNoTrie<K, V, S>
+where
+    K: Clone,
+    V: Clone,
+    S: Store<K, V>,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = VMapNoTrie::with_store();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S: Default> Default for VMapNoTrie<K, V, S> {
+    fn default() -> Self {
+        VMapNoTrie::with_store()
+    }
+}
+
+impl<K, V, S> fmt::Debug for VMapNoTrie<K, V, S>
+where
+    K: Clone + fmt::Debug,
+    V: Clone + fmt::Debug,
+    S: Store<K, V>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        crate::fmt_map(self, S::NAME, f)
+    }
+}
+
+/// Compares the live entries only, whatever the snapshots.
+impl<K, V, S> PartialEq for VMapNoTrie<K, V, S>
+where
+    V: PartialEq,
+    S: Store<K, V>,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let mut len = 0;
+        for (k, _) in self.state.iter() {
+            if let Some(v) = self.get_live(k) {
+                if other.get_live(k) != Some(v) {
+                    return false;
+                }
+                len += 1;
+            }
+        }
+        len == other
+            .state
+            .iter()
+            .filter(|(k, _)| other.get_live(k).is_some())
+            .count()
+    }
+}
+
+impl<K, V, S> Eq for VMapNoTrie<K, V, S>
+where
+    V: Eq,
+    S: Store<K, V>,
+{
+}
+
+impl<K, V, S> Index<&K> for VMapNoTrie<K, V, S>
+where
+    S: Store<K, V>,
+{
+    type Output = V;
+
+    /// Panics if `k` isn't in the live map.
+    fn index(&self, k: &K) -> &V {
+        self.get_live(k).expect("no entry found for key")
+    }
+}
+
+/// Live entries, in key order for a
+/// [`VMapNoTrieSorted`](sorted::VMapNoTrieSorted), in no particular order
+/// otherwise.
+impl<K, V, S> IntoIterator for VMapNoTrie<K, V, S>
+where
+    S: Store<K, V>,
+{
+    type Item = (K, V);
+    type IntoIter = std::vec::IntoIter<(K, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let current_ver = self.current_ver;
+        let entries: Vec<(K, V)> = self
+            .state
+            .into_iter()
+            .filter_map(|(k, mut v_map)| {
+                let (_, val) = get_version_mut(&mut v_map, current_ver);
+                val.take().map(|v| (k, v))
+            })
+            .collect();
+        entries.into_iter()
+    }
 }
+
+impl<K, V, S> crate::Diff<K, V> for VMapNoTrie<K, V, S>
+where
+    K: Clone,
+    V: Clone + PartialEq,
+    S: Store<K, V>,
+{
+    fn diff_live(&self, tag: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let found = self.snapshots.get(tag)?.num();
+        Some(self.diff_versions(self.current_ver, found))
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let from = self.snapshots.get(from)?.num();
+        let to = self.snapshots.get(to)?.num();
+        Some(self.diff_versions(from, to))
+    }
+}
+
+impl<K, V, S> VMapNoTrie<K, V, S>
+where
+    K: Clone,
+    V: Clone + PartialEq,
+    S: Store<K, V>,
+{
+    fn diff_versions(&self, from: u64, to: u64) -> Vec<crate::Change<K, V>> {
+        self.state
+            .iter()
+            .filter_map(|(k, v_map)| {
+                let (_, old) = get_version(v_map, from);
+                let (_, new) = get_version(v_map, to);
+                crate::change(k.clone(), old.as_ref(), new.as_ref())
+            })
+            .collect()
+    }
+}
+
 #[cfg(test)]
 use crate::test_helpers::common_tests::versioned_map_trait_tests;
 
 #[cfg(test)]
 versioned_map_trait_tests!(VMapNoTrie);
+
+#[cfg(test)]
+mod stats_tests {
+    use super::VMapNoTrie;
+    use crate::VersionedMap;
+
+    #[test]
+    fn stats_count_versions_and_links() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        map.insert("a".to_owned(), 1);
+        map.insert("b".to_owned(), 2);
+        map.checkpoint("ONE".to_owned());
+        map.insert("a".to_owned(), 3);
+        map.remove(&"b".to_owned());
+
+        let stats = map.stats();
+        assert_eq!(1, stats.keys);
+        assert_eq!(1, stats.snapshots);
+        assert_eq!(6, stats.version_entries);
+        assert_eq!(0, stats.link_entries);
+
+        assert!(map.rollback("ONE".to_owned()));
+        let stats = map.stats();
+        assert_eq!(2, stats.keys);
+        assert_eq!(6, stats.version_entries);
+        assert_eq!(2, stats.link_entries);
+        assert!(stats.heap_bytes > 0);
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::{VMapNoTrie, VersionId};
+    use crate::VersionedMap;
+
+    #[test]
+    fn history_resolves_rollback_links() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        let key = "key".to_owned();
+
+        map.insert(key.clone(), 1);
+        map.checkpoint("ONE".to_owned());
+        map.insert(key.clone(), 2);
+        map.checkpoint("TWO".to_owned());
+        map.remove(&key);
+        assert!(map.rollback("ONE".to_owned()));
+
+        let values: Vec<Option<&u32>> =
+            map.history(&key).into_iter().map(|(_, val)| val).collect();
+        assert_eq!(vec![Some(&1), Some(&2), None, Some(&1)], values);
+
+        let versions: Vec<VersionId> = map.history(&key).into_iter().map(|(v, _)| v).collect();
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert!(map.history(&"missing".to_owned()).is_empty());
+    }
+
+    #[test]
+    fn tags_where_value_was_seen() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        let key = "key".to_owned();
+
+        map.checkpoint("EMPTY".to_owned());
+        map.insert(key.clone(), 1);
+        map.checkpoint("ONE".to_owned());
+        map.insert(key.clone(), 2);
+        map.checkpoint("TWO".to_owned());
+        assert!(map.rollback("ONE".to_owned()));
+        map.checkpoint("ONE_AGAIN".to_owned());
+
+        assert_eq!(vec!["ONE", "ONE_AGAIN"], map.tags_where(&key, &1));
+        assert_eq!(vec!["TWO"], map.tags_where(&key, &2));
+        assert!(map.tags_where(&key, &3).is_empty());
+        assert!(map.tags_where(&"missing".to_owned(), &1).is_empty());
+    }
+
+    #[test]
+    fn address_states_by_version() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        let key = "key".to_owned();
+
+        let empty = map.checkpoint_versioned("EMPTY".to_owned());
+        map.insert(key.clone(), 1);
+        let one = map.checkpoint_versioned("ONE".to_owned());
+        map.insert(key.clone(), 2);
+        let two = map.checkpoint_versioned("TWO".to_owned());
+        map.insert(key.clone(), 3);
+
+        assert!(empty < one && one < two);
+        assert_eq!(Some(one), map.version_of("ONE"));
+        assert_eq!(None, map.version_of("MISSING"));
+
+        assert_eq!(None, map.get_at_version(empty, &key));
+        assert_eq!(Some(&1), map.get_at_version(one, &key));
+        assert_eq!(Some(&2), map.get_at_version(two, &key));
+        assert_eq!(Some(&3), map.get(&key));
+
+        assert!(map.rollback_to_version(one));
+        assert_eq!(Some(&1), map.get(&key));
+        assert_eq!(Some(&2), map.get_at_version(two, &key));
+
+        assert!(map.rollback_to_version(empty));
+        assert_eq!(None, map.get(&key));
+
+        assert!(!map.rollback_to_version(VersionId(u64::MAX)));
+    }
+
+    #[test]
+    fn freeze_without_a_tag() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        let key = "key".to_owned();
+
+        map.insert(key.clone(), 1);
+        let one = map.freeze();
+        map.insert(key.clone(), 2);
+        let two = map.freeze();
+        map.remove(&key);
+
+        assert!(one < two);
+        assert!(map.tags().is_empty());
+        assert_eq!(Some(&1), map.get_at_version(one, &key));
+        assert_eq!(Some(&2), map.get_at_version(two, &key));
+        assert_eq!(None, map.get(&key));
+
+        assert!(map.rollback_to_version(one));
+        assert_eq!(Some(&1), map.get(&key));
+        assert_eq!(Some(&2), map.get_at_version(two, &key));
+    }
+
+    #[test]
+    fn live_version_is_not_addressable() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        let key = "key".to_owned();
+
+        map.insert(key.clone(), 1);
+        let (live, _) = *map.history(&key).last().unwrap();
+        assert_eq!(None, map.get_at_version(live, &key));
+        assert!(!map.rollback_to_version(live));
+
+        assert_eq!(live, map.freeze());
+        map.insert(key.clone(), 2);
+        assert_eq!(Some(&1), map.get_at_version(live, &key));
+        assert!(map.rollback_to_version(live));
+        assert_eq!(Some(&1), map.get(&key));
+    }
+
+    #[test]
+    fn checkpoint_with_leaves_no_history() {
+        let mut map = VMapNoTrie::<String, u32>::new();
+        let key = "key".to_owned();
+
+        map.insert(key.clone(), 1);
+        map.checkpoint_with("ONE".to_owned(), vec![(key.clone(), Some(2))]);
+        map.insert(key.clone(), 3);
+        let values: Vec<Option<&u32>> =
+            map.history(&key).into_iter().map(|(_, val)| val).collect();
+        assert_eq!(vec![Some(&1), Some(&3)], values);
+
+        let one = map.version_of("ONE").unwrap();
+        assert_eq!(Some(&2), map.get_at_version(one, &key));
+        assert!(map.rollback("ONE".to_owned()));
+        assert_eq!(Some(&2), map.get(&key));
+        map.insert(key.clone(), 4);
+        assert_eq!(Some(&2), map.get_at_version(one, &key));
+
+        let values: Vec<Option<&u32>> =
+            map.history(&key).into_iter().map(|(_, val)| val).collect();
+        assert_eq!(vec![Some(&1), Some(&3), Some(&2), Some(&4)], values);
+    }
+}
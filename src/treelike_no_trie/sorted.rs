@@ -0,0 +1,113 @@
+//! [`VMapNoTrie`] with keys kept in order, for range queries on the live
+//! state and on any earlier one.
+
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+
+use super::history::History;
+use super::{get_version, VMapNoTrie, VersionId, ABSOLUTE_FIRST_VERSION};
+
+/// Same as [`VMapNoTrie`], only with keys stored in a B-tree instead of a
+/// hash map, so they need to be `Ord` rather than `Hash`.
+pub type VMapNoTrieSorted<K, V> = VMapNoTrie<K, V, BTreeMap<K, History<V>>>;
+
+impl<K, V> VMapNoTrieSorted<K, V>
+where
+    K: Ord,
+{
+    /// An empty [`VMapNoTrieSorted`], which can't be built with
+    /// [`VMapNoTrie::new`] as that one is for the hash map.
+    pub fn new_sorted() -> Self {
+        VMapNoTrie::with_store()
+    }
+
+    /// Entries of the live state with keys in `range`, in key order.
+    pub fn range<R>(&self, range: R) -> impl DoubleEndedIterator<Item = (&K, &V)>
+    where
+        R: RangeBounds<K>,
+    {
+        self.range_at_ver(self.current_ver, range)
+    }
+
+    /// Entries with keys in `range` as they were at `version`, in key
+    /// order; none unless `version` is frozen.
+    pub fn range_at<R>(
+        &self,
+        version: VersionId,
+        range: R,
+    ) -> impl DoubleEndedIterator<Item = (&K, &V)>
+    where
+        R: RangeBounds<K>,
+    {
+        // nothing is stored at the first version
+        let version = if version.0 >= self.current_ver {
+            ABSOLUTE_FIRST_VERSION
+        } else {
+            version.0
+        };
+        self.range_at_ver(version, range)
+    }
+
+    /// Entries with keys in `range` as they were when `tag` was
+    /// checkpointed, in key order.
+    pub fn range_at_tag<R>(
+        &self,
+        tag: &str,
+        range: R,
+    ) -> Option<impl DoubleEndedIterator<Item = (&K, &V)>>
+    where
+        R: RangeBounds<K>,
+    {
+        let version = self.snapshots.get(tag)?.num();
+        Some(self.range_at_ver(version, range))
+    }
+
+    fn range_at_ver<R>(&self, version: u64, range: R) -> impl DoubleEndedIterator<Item = (&K, &V)>
+    where
+        R: RangeBounds<K>,
+    {
+        self.state.range(range).filter_map(move |(k, v_map)| {
+            let (_, val) = get_version(v_map, version);
+            val.as_ref().map(|v| (k, v))
+        })
+    }
+}
+
+#[cfg(test)]
+use crate::test_helpers::common_tests::versioned_map_trait_tests;
+
+#[cfg(test)]
+versioned_map_trait_tests!(VMapNoTrieSorted, new_sorted);
+
+#[cfg(test)]
+mod range_tests {
+    use super::VMapNoTrieSorted;
+    use crate::treelike_no_trie::VersionId;
+    use crate::VersionedMap;
+
+    #[test]
+    fn ranges_at_any_version() {
+        let mut map = VMapNoTrieSorted::<u32, &str>::new_sorted();
+        for k in [5, 1, 3, 9, 7] {
+            map.insert(k, "old");
+        }
+        let old = map.checkpoint_versioned("OLD".to_owned());
+        map.insert(3, "new");
+        map.remove(&7);
+        map.insert(4, "new");
+
+        let live: Vec<(&u32, &&str)> = map.range(2..8).collect();
+        assert_eq!(vec![(&3, &"new"), (&4, &"new"), (&5, &"old")], live);
+
+        let then: Vec<(&u32, &&str)> = map.range_at_tag("OLD", 2..8).unwrap().collect();
+        assert_eq!(vec![(&3, &"old"), (&5, &"old"), (&7, &"old")], then);
+        assert_eq!(then, map.range_at(old, 2..8).collect::<Vec<_>>());
+        assert!(map.range_at_tag("MISSING", ..).is_none());
+        assert_eq!(0, map.range_at(VersionId(u64::MAX), ..).count());
+
+        assert!(map.rollback("OLD".to_owned()));
+        let keys: Vec<u32> = map.entries().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(vec![1, 3, 5, 7, 9], keys);
+        assert_eq!(Some((&9, &"old")), map.range(6..).next_back());
+    }
+}
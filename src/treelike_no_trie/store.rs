@@ -0,0 +1,141 @@
+use std::collections::{btree_map, hash_map, BTreeMap, HashMap};
+use std::hash::Hash;
+use std::mem;
+
+use super::history::History;
+
+/// Where a [`VMapNoTrie`](super::VMapNoTrie) keeps the history of every
+/// key: a hash map, or a B-tree for [`VMapNoTrieSorted`](super::sorted::VMapNoTrieSorted).
+pub trait Store<K, V>: Default {
+    type Entry<'a>: Slot<'a, K, V>
+    where
+        Self: 'a;
+
+    /// Name of the map for `Debug`.
+    const NAME: &'static str;
+
+    fn get(&self, k: &K) -> Option<&History<V>>;
+    fn get_mut(&mut self, k: &K) -> Option<&mut History<V>>;
+    fn insert(&mut self, k: K, v_map: History<V>);
+    fn entry(&mut self, k: K) -> Self::Entry<'_>;
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &History<V>)> + '_>;
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut History<V>> + '_>;
+    fn into_iter<'a>(self) -> Box<dyn Iterator<Item = (K, History<V>)> + 'a>
+    where
+        Self: 'a;
+    /// Approximate size of the table, not counting memory owned by the
+    /// keys and histories themselves.
+    fn heap_bytes(&self) -> usize;
+}
+
+/// The entry of a key in a [`Store`].
+pub trait Slot<'a, K, V> {
+    fn key(&self) -> &K;
+    fn get_mut(&mut self) -> Option<&mut History<V>>;
+    fn or_insert_with<F: FnOnce() -> History<V>>(self, f: F) -> &'a mut History<V>;
+}
+
+impl<K: Eq + Hash, V> Store<K, V> for HashMap<K, History<V>> {
+    type Entry<'a>
+        = hash_map::Entry<'a, K, History<V>>
+    where
+        Self: 'a;
+
+    const NAME: &'static str = "VMapNoTrie";
+
+    fn get(&self, k: &K) -> Option<&History<V>> {
+        HashMap::get(self, k)
+    }
+    fn get_mut(&mut self, k: &K) -> Option<&mut History<V>> {
+        HashMap::get_mut(self, k)
+    }
+    fn insert(&mut self, k: K, v_map: History<V>) {
+        HashMap::insert(self, k, v_map);
+    }
+    fn entry(&mut self, k: K) -> Self::Entry<'_> {
+        HashMap::entry(self, k)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &History<V>)> + '_> {
+        Box::new(HashMap::iter(self))
+    }
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut History<V>> + '_> {
+        Box::new(HashMap::values_mut(self))
+    }
+    fn into_iter<'a>(self) -> Box<dyn Iterator<Item = (K, History<V>)> + 'a>
+    where
+        Self: 'a,
+    {
+        Box::new(IntoIterator::into_iter(self))
+    }
+    fn heap_bytes(&self) -> usize {
+        crate::hash_map_bytes(self)
+    }
+}
+
+impl<K: Ord, V> Store<K, V> for BTreeMap<K, History<V>> {
+    type Entry<'a>
+        = btree_map::Entry<'a, K, History<V>>
+    where
+        Self: 'a;
+
+    const NAME: &'static str = "VMapNoTrieSorted";
+
+    fn get(&self, k: &K) -> Option<&History<V>> {
+        BTreeMap::get(self, k)
+    }
+    fn get_mut(&mut self, k: &K) -> Option<&mut History<V>> {
+        BTreeMap::get_mut(self, k)
+    }
+    fn insert(&mut self, k: K, v_map: History<V>) {
+        BTreeMap::insert(self, k, v_map);
+    }
+    fn entry(&mut self, k: K) -> Self::Entry<'_> {
+        BTreeMap::entry(self, k)
+    }
+    fn iter(&self) -> Box<dyn Iterator<Item = (&K, &History<V>)> + '_> {
+        Box::new(BTreeMap::iter(self))
+    }
+    fn values_mut(&mut self) -> Box<dyn Iterator<Item = &mut History<V>> + '_> {
+        Box::new(BTreeMap::values_mut(self))
+    }
+    fn into_iter<'a>(self) -> Box<dyn Iterator<Item = (K, History<V>)> + 'a>
+    where
+        Self: 'a,
+    {
+        Box::new(IntoIterator::into_iter(self))
+    }
+    fn heap_bytes(&self) -> usize {
+        // nodes are about two thirds full on average
+        self.len() * mem::size_of::<(K, History<V>)>() * 3 / 2
+    }
+}
+
+impl<'a, K, V> Slot<'a, K, V> for hash_map::Entry<'a, K, History<V>> {
+    fn key(&self) -> &K {
+        hash_map::Entry::key(self)
+    }
+    fn get_mut(&mut self) -> Option<&mut History<V>> {
+        match self {
+            hash_map::Entry::Occupied(entry) => Some(entry.get_mut()),
+            hash_map::Entry::Vacant(_) => None,
+        }
+    }
+    fn or_insert_with<F: FnOnce() -> History<V>>(self, f: F) -> &'a mut History<V> {
+        hash_map::Entry::or_insert_with(self, f)
+    }
+}
+
+impl<'a, K: Ord, V> Slot<'a, K, V> for btree_map::Entry<'a, K, History<V>> {
+    fn key(&self) -> &K {
+        btree_map::Entry::key(self)
+    }
+    fn get_mut(&mut self) -> Option<&mut History<V>> {
+        match self {
+            btree_map::Entry::Occupied(entry) => Some(entry.get_mut()),
+            btree_map::Entry::Vacant(_) => None,
+        }
+    }
+    fn or_insert_with<F: FnOnce() -> History<V>>(self, f: F) -> &'a mut History<V> {
+        btree_map::Entry::or_insert_with(self, f)
+    }
+}
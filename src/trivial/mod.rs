@@ -1,8 +1,10 @@
-use std::collections::HashMap;
+use std::collections::hash_map::{self, HashMap};
+use std::fmt;
 use std::hash::Hash;
+use std::ops::Index;
 
 #[allow(unused)]
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Hash, Eq, PartialEq, Clone)]
 enum Version {
     Latest,
     Tagged(String),
@@ -12,12 +14,12 @@ enum Version {
 type VersionedMapTrivial<K, V> = VMapTriv<K, V>;
 
 #[allow(unused)]
+#[derive(Clone)]
 pub struct VMapTriv<K, V> {
     inner: HashMap<Version, HashMap<K, V>>,
 }
 
 impl<K, V> VMapTriv<K, V> {
-    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let mut map = HashMap::<Version, HashMap<K, V>>::new();
         map.insert(Version::Latest, HashMap::new());
@@ -25,6 +27,58 @@ impl<K, V> VMapTriv<K, V> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub keys: usize,
+    pub snapshots: usize,
+    /// entries of the live map and of all snapshots together
+    pub stored_entries: usize,
+    /// approximate, not counting memory owned by keys and values
+    pub heap_bytes: usize,
+}
+
+impl<K, V> VMapTriv<K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn stats(&self) -> Stats {
+        let latest_map = self.inner.get(&Version::Latest).unwrap();
+        let tags_bytes: usize = self
+            .inner
+            .keys()
+            .map(|version| match version {
+                Version::Latest => 0,
+                Version::Tagged(tag) => tag.capacity(),
+            })
+            .sum();
+        Stats {
+            keys: latest_map.len(),
+            snapshots: self.inner.len() - 1,
+            stored_entries: self.inner.values().map(HashMap::len).sum(),
+            heap_bytes: crate::hash_map_bytes(&self.inner)
+                + self.inner.values().map(crate::hash_map_bytes).sum::<usize>()
+                + tags_bytes,
+        }
+    }
+
+    pub fn entry(&mut self, k: K) -> hash_map::Entry<'_, K, V> {
+        let latest_map = self.inner.get_mut(&Version::Latest).unwrap();
+        latest_map.entry(k)
+    }
+
+    /// Applies `f` to the value of `k` in place, returns whether `k` was present.
+    pub fn update<F: FnOnce(&mut V)>(&mut self, k: &K, f: F) -> bool {
+        let latest_map = self.inner.get_mut(&Version::Latest).unwrap();
+        match latest_map.get_mut(k) {
+            Some(v) => {
+                f(v);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 impl<K, V> super::VersionedMap<K, V> for VMapTriv<K, V>
 where
     K: Eq + Hash + Clone,
@@ -68,12 +122,153 @@ where
         false
     }
 
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String> {
+        let snapshot = self.inner.get(&Version::Tagged(tag))?.clone();
+        let saved = save_as.unwrap_or_else(|| {
+            crate::autosave_tag(|tag| self.inner.contains_key(&Version::Tagged(tag.to_owned())))
+        });
+        let latest_map = self.inner.insert(Version::Latest, snapshot).unwrap();
+        self.inner.insert(Version::Tagged(saved.clone()), latest_map);
+        Some(saved)
+    }
+
     fn prune(&mut self) {
         let latest = self.inner.remove(&Version::Latest).unwrap();
 
         for (_k, _v) in self.inner.drain() {}
         self.inner.insert(Version::Latest, latest);
     }
+
+    fn tags(&self) -> Vec<&str> {
+        let mut tags: Vec<&str> = self
+            .inner
+            .keys()
+            .filter_map(|version| match version {
+                Version::Latest => None,
+                Version::Tagged(tag) => Some(tag.as_str()),
+            })
+            .collect();
+        tags.sort_unstable();
+        tags
+    }
+
+    fn entries(&self) -> Vec<(K, &V)> {
+        let latest_map = self.inner.get(&Version::Latest).unwrap();
+        latest_map.iter().map(|(k, v)| (k.clone(), v)).collect()
+    }
+}
+
+impl<K, V> Extend<(K, V)> for VMapTriv<K, V>
+where
+    K: Eq + Hash,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        let latest_map = self.inner.get_mut(&Version::Latest).unwrap();
+        latest_map.extend(iter);
+    }
+}
+
+impl<K, V> FromIterator<(K, V)> for VMapTriv<K, V>
+where
+    K: Eq + Hash,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = VMapTriv::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V> Default for VMapTriv<K, V> {
+    fn default() -> Self {
+        VMapTriv::new()
+    }
+}
+
+impl<K, V> fmt::Debug for VMapTriv<K, V>
+where
+    K: Eq + Hash + Clone + fmt::Debug,
+    V: Clone + fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        crate::fmt_map(self, "VMapTriv", f)
+    }
+}
+
+/// Compares the live entries only, whatever the snapshots.
+impl<K, V> PartialEq for VMapTriv<K, V>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.inner.get(&Version::Latest) == other.inner.get(&Version::Latest)
+    }
+}
+
+impl<K, V> Eq for VMapTriv<K, V>
+where
+    K: Eq + Hash,
+    V: Eq,
+{
+}
+
+impl<K, V> Index<&K> for VMapTriv<K, V>
+where
+    K: Eq + Hash,
+{
+    type Output = V;
+
+    /// Panics if `k` isn't in the live map.
+    fn index(&self, k: &K) -> &V {
+        &self.inner[&Version::Latest][k]
+    }
+}
+
+/// Live entries, in no particular order.
+impl<K, V> IntoIterator for VMapTriv<K, V>
+where
+    K: Eq + Hash,
+{
+    type Item = (K, V);
+    type IntoIter = hash_map::IntoIter<K, V>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.inner.remove(&Version::Latest).unwrap().into_iter()
+    }
+}
+
+impl<K, V> crate::Diff<K, V> for VMapTriv<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone + PartialEq,
+{
+    fn diff_live(&self, tag: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let snapshot = self.inner.get(&Version::Tagged(tag.to_owned()))?;
+        let latest_map = self.inner.get(&Version::Latest).unwrap();
+        Some(diff_maps(latest_map, snapshot))
+    }
+
+    fn diff(&self, from: &str, to: &str) -> Option<Vec<crate::Change<K, V>>> {
+        let from = self.inner.get(&Version::Tagged(from.to_owned()))?;
+        let to = self.inner.get(&Version::Tagged(to.to_owned()))?;
+        Some(diff_maps(from, to))
+    }
+}
+
+fn diff_maps<K, V>(old: &HashMap<K, V>, new: &HashMap<K, V>) -> Vec<crate::Change<K, V>>
+where
+    K: Eq + Hash + Clone,
+    V: Clone + PartialEq,
+{
+    let changed = old
+        .iter()
+        .filter_map(|(k, v)| crate::change(k.clone(), Some(v), new.get(k)));
+    let inserted = new
+        .iter()
+        .filter(|(k, _)| !old.contains_key(k))
+        .filter_map(|(k, v)| crate::change(k.clone(), None, Some(v)));
+    changed.chain(inserted).collect()
 }
 
 #[cfg(test)]
@@ -81,3 +276,28 @@ use crate::test_helpers::common_tests::versioned_map_trait_tests;
 
 #[cfg(test)]
 versioned_map_trait_tests!(VMapTriv);
+
+#[cfg(test)]
+mod stats_tests {
+    use super::VMapTriv;
+    use crate::VersionedMap;
+
+    #[test]
+    fn stats_count_every_copy() {
+        let mut map = VMapTriv::<String, u32>::new();
+        map.insert("a".to_owned(), 1);
+        map.insert("b".to_owned(), 2);
+        map.checkpoint("ONE".to_owned());
+        map.remove(&"a".to_owned());
+
+        let stats = map.stats();
+        assert_eq!(1, stats.keys);
+        assert_eq!(1, stats.snapshots);
+        assert_eq!(3, stats.stored_entries);
+        assert!(stats.heap_bytes > 0);
+
+        map.prune();
+        assert_eq!(0, map.stats().snapshots);
+        assert_eq!(1, map.stats().stored_entries);
+    }
+}
@@ -0,0 +1,381 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::treelike::AsFromBytes;
+use crate::{Change, Diff, VersionedMap};
+
+/// Identifies a subscription to [`Watched::unsubscribe`] it with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+enum Filter {
+    Key(Vec<u8>),
+    Prefix(Vec<u8>),
+}
+
+impl Filter {
+    fn matches(&self, key: &[u8]) -> bool {
+        match self {
+            Filter::Key(bytes) => bytes == key,
+            Filter::Prefix(prefix) => key.starts_with(prefix),
+        }
+    }
+}
+
+type Callback<K, V> = Box<dyn FnMut(&Change<K, V>)>;
+
+enum Sink<K, V> {
+    Callback(Callback<K, V>),
+    Channel(Sender<Change<K, V>>),
+}
+
+struct Subscription<K, V> {
+    id: SubscriptionId,
+    filter: Filter,
+    sink: Sink<K, V>,
+}
+
+/// Wraps a [`VersionedMap`], notifying subscribers of the changes made to
+/// the keys they watch, including the ones made by rolling back.
+/// Inserting the value a key already has isn't a change.
+pub struct Watched<M, K, V> {
+    inner: M,
+    next_id: u64,
+    subscriptions: Vec<Subscription<K, V>>,
+}
+
+impl<M, K, V> Watched<M, K, V> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            next_id: 0,
+            subscriptions: vec![],
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Returns whether `id` was subscribed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let before = self.subscriptions.len();
+        self.subscriptions.retain(|sub| sub.id != id);
+        self.subscriptions.len() != before
+    }
+
+    fn subscribe(&mut self, filter: Filter, sink: Sink<K, V>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscriptions.push(Subscription { id, filter, sink });
+        id
+    }
+}
+
+impl<M, K, V> Watched<M, K, V>
+where
+    K: AsFromBytes,
+{
+    pub fn watch_key<F>(&mut self, key: &K, f: F) -> SubscriptionId
+    where
+        F: FnMut(&Change<K, V>) + 'static,
+    {
+        let filter = Filter::Key(key.as_bytes().to_vec());
+        self.subscribe(filter, Sink::Callback(Box::new(f)))
+    }
+
+    /// Calls `f` on changes to the keys starting with `prefix`.
+    pub fn watch_prefix<F>(&mut self, prefix: &K, f: F) -> SubscriptionId
+    where
+        F: FnMut(&Change<K, V>) + 'static,
+    {
+        let filter = Filter::Prefix(prefix.as_bytes().to_vec());
+        self.subscribe(filter, Sink::Callback(Box::new(f)))
+    }
+
+    /// The subscription ends by itself once the receiver is dropped.
+    pub fn subscribe_key(&mut self, key: &K) -> (SubscriptionId, Receiver<Change<K, V>>) {
+        let (tx, rx) = mpsc::channel();
+        let filter = Filter::Key(key.as_bytes().to_vec());
+        (self.subscribe(filter, Sink::Channel(tx)), rx)
+    }
+
+    /// Same as [`Watched::subscribe_key`] for the keys starting with `prefix`.
+    pub fn subscribe_prefix(&mut self, prefix: &K) -> (SubscriptionId, Receiver<Change<K, V>>) {
+        let (tx, rx) = mpsc::channel();
+        let filter = Filter::Prefix(prefix.as_bytes().to_vec());
+        (self.subscribe(filter, Sink::Channel(tx)), rx)
+    }
+
+    fn notify(&mut self, change: Change<K, V>)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let key = change.key().as_bytes();
+        self.subscriptions.retain_mut(|sub| {
+            if !sub.filter.matches(key) {
+                return true;
+            }
+            match &mut sub.sink {
+                Sink::Callback(f) => {
+                    f(&change);
+                    true
+                }
+                Sink::Channel(tx) => tx.send(change.clone()).is_ok(),
+            }
+        });
+    }
+}
+
+impl<M, K, V> VersionedMap<K, V> for Watched<M, K, V>
+where
+    M: VersionedMap<K, V> + Diff<K, V>,
+    K: AsFromBytes + Clone,
+    V: Clone + PartialEq,
+{
+    fn insert(&mut self, k: K, v: V) -> Option<V> {
+        if self.subscriptions.is_empty() {
+            return self.inner.insert(k, v);
+        }
+        let old = self.inner.insert(k.clone(), v.clone());
+        if let Some(change) = crate::change(k, old.as_ref(), Some(&v)) {
+            self.notify(change);
+        }
+        old
+    }
+
+    fn get(&self, k: &K) -> Option<&V> {
+        self.inner.get(k)
+    }
+
+    fn remove(&mut self, k: &K) -> Option<V> {
+        let old = self.inner.remove(k);
+        if let Some(old) = &old {
+            self.notify(Change::Removed {
+                key: k.clone(),
+                old: old.clone(),
+            });
+        }
+        old
+    }
+
+    fn checkpoint(&mut self, tag: String) {
+        self.inner.checkpoint(tag)
+    }
+
+    fn rollback(&mut self, tag: String) -> bool {
+        if self.subscriptions.is_empty() {
+            return self.inner.rollback(tag);
+        }
+        let changes = match self.inner.diff_live(&tag) {
+            Some(changes) => changes,
+            None => return false,
+        };
+        let done = self.inner.rollback(tag);
+        for change in changes {
+            self.notify(change);
+        }
+        done
+    }
+
+    fn rollback_preserving(&mut self, tag: String, save_as: Option<String>) -> Option<String> {
+        if self.subscriptions.is_empty() {
+            return self.inner.rollback_preserving(tag, save_as);
+        }
+        let changes = self.inner.diff_live(&tag)?;
+        let saved = self.inner.rollback_preserving(tag, save_as);
+        for change in changes {
+            self.notify(change);
+        }
+        saved
+    }
+
+    fn prune(&mut self) {
+        self.inner.prune()
+    }
+
+    /// Leaves the live state as it is, so notifies nobody.
+    fn checkpoint_with(&mut self, tag: String, ops: Vec<(K, Option<V>)>) {
+        self.inner.checkpoint_with(tag, ops)
+    }
+
+    fn tags(&self) -> Vec<&str> {
+        self.inner.tags()
+    }
+
+    fn entries(&self) -> Vec<(K, &V)> {
+        self.inner.entries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::Watched;
+    use crate::treelike::VMapTree;
+    use crate::treelike_no_trie::VMapNoTrie;
+    use crate::trivial::VMapTriv;
+    use crate::{Change, Diff, VersionedMap};
+
+    fn inserted(key: &str, value: u32) -> Change<String, u32> {
+        Change::Inserted {
+            key: key.to_owned(),
+            value,
+        }
+    }
+
+    fn sorted(mut changes: Vec<Change<String, u32>>) -> Vec<Change<String, u32>> {
+        changes.sort_by(|a, b| a.key().cmp(b.key()));
+        changes
+    }
+
+    fn diff_against_checkpoint<M>(mut map: M)
+    where
+        M: VersionedMap<String, u32> + Diff<String, u32>,
+    {
+        for (i, key) in ["a", "ab", "abc", "b", "c"].iter().enumerate() {
+            map.insert(key.to_string(), i as u32);
+        }
+        map.checkpoint("ONE".to_owned());
+        map.insert("ab".to_owned(), 10);
+        map.remove(&"b".to_owned());
+        map.insert("abcd".to_owned(), 11);
+        map.insert("c".to_owned(), 4);
+
+        let expected = vec![
+            Change::Updated {
+                key: "ab".to_owned(),
+                old: 10,
+                new: 1,
+            },
+            Change::Removed {
+                key: "abcd".to_owned(),
+                old: 11,
+            },
+            inserted("b", 3),
+        ];
+        assert_eq!(None, map.diff_live("UNKNOWN"));
+        assert_eq!(expected, sorted(map.diff_live("ONE").unwrap()));
+
+        map.checkpoint("TWO".to_owned());
+        assert_eq!(None, map.diff("ONE", "UNKNOWN"));
+        assert_eq!(expected, sorted(map.diff("TWO", "ONE").unwrap()));
+        assert_eq!(Some(vec![]), map.diff("TWO", "TWO"));
+    }
+
+    #[test]
+    fn diff_skips_unchanged_keys() {
+        diff_against_checkpoint(VMapTriv::new());
+        diff_against_checkpoint(VMapTree::new());
+        diff_against_checkpoint(VMapNoTrie::new());
+    }
+
+    fn rollback_notifies_watchers<M>(map: M)
+    where
+        M: VersionedMap<String, u32> + Diff<String, u32>,
+    {
+        let mut map = Watched::new(map);
+        let seen = Rc::new(RefCell::new(vec![]));
+        let sink = seen.clone();
+        map.watch_prefix(&"user/".to_owned(), move |change| {
+            sink.borrow_mut().push(change.clone())
+        });
+        let (_, rx) = map.subscribe_key(&"user/1".to_owned());
+
+        map.insert("user/1".to_owned(), 1);
+        map.insert("group/1".to_owned(), 1);
+        map.checkpoint("ONE".to_owned());
+        map.insert("user/1".to_owned(), 1);
+        map.insert("user/2".to_owned(), 2);
+        map.remove(&"user/1".to_owned());
+        assert!(map.rollback("ONE".to_owned()));
+
+        assert_eq!(
+            vec![
+                inserted("user/1", 1),
+                inserted("user/2", 2),
+                Change::Removed {
+                    key: "user/1".to_owned(),
+                    old: 1,
+                },
+            ],
+            seen.borrow()[..3]
+        );
+        assert_eq!(
+            vec![
+                inserted("user/1", 1),
+                Change::Removed {
+                    key: "user/2".to_owned(),
+                    old: 2,
+                },
+            ],
+            sorted(seen.borrow()[3..].to_vec())
+        );
+        assert_eq!(
+            vec![
+                inserted("user/1", 1),
+                Change::Removed {
+                    key: "user/1".to_owned(),
+                    old: 1,
+                },
+                inserted("user/1", 1),
+            ],
+            rx.try_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn rollback_emits_diff() {
+        rollback_notifies_watchers(VMapTriv::new());
+        rollback_notifies_watchers(VMapTree::new());
+        rollback_notifies_watchers(VMapNoTrie::new());
+    }
+
+    #[test]
+    fn checkpoint_with_notifies_nobody() {
+        let mut map = Watched::new(VMapNoTrie::<String, u32>::new());
+        let (_, rx) = map.subscribe_prefix(&"".to_owned());
+        map.insert("a".to_owned(), 1);
+        map.checkpoint_with(
+            "ONE".to_owned(),
+            vec![("a".to_owned(), None), ("b".to_owned(), Some(2))],
+        );
+        assert_eq!(vec![inserted("a", 1)], rx.try_iter().collect::<Vec<_>>());
+
+        assert!(map.rollback("ONE".to_owned()));
+        assert_eq!(
+            vec![
+                Change::Removed {
+                    key: "a".to_owned(),
+                    old: 1,
+                },
+                inserted("b", 2),
+            ],
+            sorted(rx.try_iter().collect())
+        );
+    }
+
+    #[test]
+    fn subscriptions_end() {
+        let mut map = Watched::new(VMapTree::<String, u32>::new());
+        let seen = Rc::new(RefCell::new(0));
+        let sink = seen.clone();
+        let id = map.watch_key(&"a".to_owned(), move |_| *sink.borrow_mut() += 1);
+        let (_, rx) = map.subscribe_prefix(&"a".to_owned());
+
+        map.insert("a".to_owned(), 1);
+        assert!(map.unsubscribe(id));
+        assert!(!map.unsubscribe(id));
+        drop(rx);
+        map.insert("a".to_owned(), 2);
+
+        assert_eq!(1, *seen.borrow());
+        assert!(map.subscriptions.is_empty());
+    }
+}